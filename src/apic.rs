@@ -0,0 +1,333 @@
+// An interrupt backend built on the local APIC and I/O APIC, as an alternative to the 8259
+// PICs. The I/O APIC is located through the ACPI MADT, and legacy IRQ lines are routed to the
+// same vectors the PICs would use, so the rest of the crate handles them identically. The
+// local APIC timer replaces the PIT as the source of the timer interrupt.
+//
+// APIC registers and ACPI tables live at physical addresses, so this backend needs the
+// bootloader to map all of physical memory. Section numbers in the comments below refer to the
+// ACPI specification.
+
+use core::ptr::{read_unaligned, read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::registers::model_specific::Msr;
+
+use crate::{interrupts, pit};
+
+/// The vector the local APIC uses for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const MAX_IO_APICS: usize = 4;
+const NUM_IRQS: usize = interrupts::NUM_IRQS as usize;
+
+// Local APIC register offsets.
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_TASK_PRIORITY: u64 = 0x80;
+const LAPIC_EOI: u64 = 0xB0;
+const LAPIC_SPURIOUS: u64 = 0xF0;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_LVT_LINT0: u64 = 0x350;
+const LAPIC_TIMER_INITIAL: u64 = 0x380;
+const LAPIC_TIMER_CURRENT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3E0;
+
+const LAPIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// I/O APIC registers, reached through a select register and a data window.
+const IO_APIC_SELECT: u64 = 0x00;
+const IO_APIC_WINDOW: u64 = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_APIC_REDIRECTION: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+// About 10 ms of PIT cycles, for calibrating the local APIC timer.
+const CALIBRATION_CYCLES: u16 = 11_932;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHYSICAL_OFFSET: AtomicU64 = AtomicU64::new(0);
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+static IRQ_MASK: AtomicU16 = AtomicU16::new(0xFFFF);
+
+#[derive(Copy, Clone)]
+struct IoApic {
+    address: u64,
+    gsi_base: u32,
+}
+
+// A legacy IRQ line's Interrupt Source Override from the MADT.
+#[derive(Copy, Clone)]
+struct SourceOverride {
+    gsi: u32,
+    flags: u16,
+}
+
+// Where a legacy IRQ line ends up in an I/O APIC, and the low half of its redirection entry.
+#[derive(Copy, Clone)]
+struct Route {
+    io_apic: u64,
+    entry: u32,
+    low: u32,
+}
+
+struct Madt {
+    local_apic: u64,
+    io_apics: [Option<IoApic>; MAX_IO_APICS],
+    overrides: [Option<SourceOverride>; NUM_IRQS],
+}
+
+lazy_static! {
+    static ref ROUTES: Mutex<[Option<Route>; NUM_IRQS]> = Mutex::new([None; NUM_IRQS]);
+}
+
+/// Returns true if interrupts are being delivered through the APICs rather than the PICs.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Disables the PICs and switches interrupt delivery over to the APICs. The local APIC timer
+/// is set to fire at the rate the PIT would for **timer_hz**, and the legacy IRQ lines whose
+/// bits are clear in **irq_mask** are enabled.
+///
+/// It will **panic** if the ACPI tables do not describe a local APIC and an I/O APIC.
+pub(crate) fn init(physical_memory_offset: u64, timer_hz: Option<u32>, irq_mask: u16) {
+    PHYSICAL_OFFSET.store(physical_memory_offset, Ordering::SeqCst);
+    let madt = find_rsdp()
+        .and_then(find_madt)
+        .map(parse_madt)
+        .expect("APIC backend requested, but the ACPI tables have no MADT");
+    assert!(
+        madt.io_apics[0].is_some(),
+        "APIC backend requested, but the MADT lists no I/O APIC"
+    );
+
+    unsafe {
+        interrupts::PICS.lock().disable();
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        let value = apic_base.read();
+        apic_base.write(value | APIC_GLOBAL_ENABLE);
+    }
+    LOCAL_APIC.store(madt.local_apic, Ordering::SeqCst);
+    // Keep the disabled PICs from reaching the CPU at all, even with spurious interrupts.
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_TASK_PRIORITY, 0);
+    lapic_write(LAPIC_SPURIOUS, LAPIC_ENABLE | SPURIOUS_VECTOR as u32);
+
+    let divisor = timer_hz.map_or(pit::MAX_DIVISOR, pit::divisor_for);
+    start_timer(divisor);
+    pit::set_tick_divisor(divisor);
+
+    route_irqs(&madt, irq_mask);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Signals the end of an interrupt to the local APIC.
+pub(crate) fn end_of_interrupt() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Returns the mask of legacy IRQ lines, in the format of **interrupts::irq_mask()**.
+pub(crate) fn irq_mask() -> u16 {
+    IRQ_MASK.load(Ordering::SeqCst)
+}
+
+/// Masks and unmasks the legacy IRQ lines in the I/O APIC, in the format of
+/// **interrupts::set_irq_mask()**. Lines 0 and 2 are never routed, as the local APIC timer
+/// replaces the PIT and the PICs are no longer chained.
+pub(crate) fn set_irq_mask(mask: u16) {
+    IRQ_MASK.store(mask, Ordering::SeqCst);
+    let routes = ROUTES.lock();
+    for (line, route) in routes.iter().enumerate() {
+        if let Some(route) = route {
+            let masked = if mask & (1 << line) != 0 {
+                REDIRECTION_MASKED
+            } else {
+                0
+            };
+            io_apic_write(
+                route.io_apic,
+                IO_APIC_REDIRECTION + 2 * route.entry,
+                route.low | masked,
+            );
+        }
+    }
+}
+
+// Measures the local APIC timer against PIT channel 2, then starts it in periodic mode with a
+// period of **divisor** PIT cycles.
+fn start_timer(divisor: u32) {
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    pit::start_stopwatch(CALIBRATION_CYCLES);
+    lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+    while !pit::stopwatch_done() {}
+    let elapsed = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+
+    let count = elapsed as u64 * divisor as u64 / CALIBRATION_CYCLES as u64;
+    lapic_write(
+        LAPIC_LVT_TIMER,
+        LVT_PERIODIC | interrupts::irq_vector(0) as u32,
+    );
+    lapic_write(LAPIC_TIMER_INITIAL, count.clamp(1, u32::MAX as u64) as u32);
+}
+
+fn route_irqs(madt: &Madt, irq_mask: u16) {
+    let destination = lapic_read(LAPIC_ID) & 0xFF00_0000;
+    let mut routes = ROUTES.lock();
+    for line in 1..NUM_IRQS {
+        if line == interrupts::CASCADE_IRQ as usize {
+            continue;
+        }
+        // ISA interrupts are edge-triggered and active high unless overridden (ACPI 5.2.12.5).
+        let (gsi, flags) = madt.overrides[line].map_or((line as u32, 0), |o| (o.gsi, o.flags));
+        let Some(io_apic) =
+            madt.io_apics.iter().flatten().find(|io_apic| {
+                gsi >= io_apic.gsi_base && gsi - io_apic.gsi_base < entries(io_apic)
+            })
+        else {
+            continue;
+        };
+        let mut low = interrupts::irq_vector(line as u8) as u32;
+        if flags & 0b11 == 0b11 {
+            low |= REDIRECTION_ACTIVE_LOW;
+        }
+        if (flags >> 2) & 0b11 == 0b11 {
+            low |= REDIRECTION_LEVEL;
+        }
+        let route = Route {
+            io_apic: io_apic.address,
+            entry: gsi - io_apic.gsi_base,
+            low,
+        };
+        io_apic_write(
+            route.io_apic,
+            IO_APIC_REDIRECTION + 2 * route.entry + 1,
+            destination,
+        );
+        routes[line] = Some(route);
+    }
+    drop(routes);
+    set_irq_mask(irq_mask);
+}
+
+fn entries(io_apic: &IoApic) -> u32 {
+    ((io_apic_read(io_apic.address, IO_APIC_VERSION) >> 16) & 0xFF) + 1
+}
+
+fn virt(physical: u64) -> u64 {
+    PHYSICAL_OFFSET.load(Ordering::SeqCst) + physical
+}
+
+fn lapic_read(register: u64) -> u32 {
+    let address = virt(LOCAL_APIC.load(Ordering::SeqCst) + register);
+    unsafe { read_volatile(address as *const u32) }
+}
+
+fn lapic_write(register: u64, value: u32) {
+    let address = virt(LOCAL_APIC.load(Ordering::SeqCst) + register);
+    unsafe { write_volatile(address as *mut u32, value) }
+}
+
+fn io_apic_read(io_apic: u64, register: u32) -> u32 {
+    unsafe {
+        write_volatile(virt(io_apic + IO_APIC_SELECT) as *mut u32, register);
+        read_volatile(virt(io_apic + IO_APIC_WINDOW) as *const u32)
+    }
+}
+
+fn io_apic_write(io_apic: u64, register: u32, value: u32) {
+    unsafe {
+        write_volatile(virt(io_apic + IO_APIC_SELECT) as *mut u32, register);
+        write_volatile(virt(io_apic + IO_APIC_WINDOW) as *mut u32, value);
+    }
+}
+
+fn read<T: Copy>(physical: u64) -> T {
+    unsafe { read_unaligned(virt(physical) as *const T) }
+}
+
+fn checksum(physical: u64, len: u64) -> u8 {
+    (0..len).fold(0, |sum, i| sum.wrapping_add(read::<u8>(physical + i)))
+}
+
+// The RSDP is on a 16-byte boundary in the first KiB of the EBDA or in the BIOS area
+// (ACPI 5.2.5.1).
+fn find_rsdp() -> Option<u64> {
+    let ebda = (read::<u16>(0x40E) as u64) << 4;
+    let regions = [(ebda, ebda + 0x400), (0xE0000, 0x100000)];
+    regions
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&address| read::<[u8; 8]>(address) == *b"RSD PTR " && checksum(address, 20) == 0)
+}
+
+// Searches the XSDT, or the RSDT on ACPI 1.0 systems, for the MADT (ACPI 5.2.7, 5.2.8).
+fn find_madt(rsdp: u64) -> Option<u64> {
+    let revision = read::<u8>(rsdp + 15);
+    let (sdt, entry_size) = if revision >= 2 {
+        (read::<u64>(rsdp + 24), 8)
+    } else {
+        (read::<u32>(rsdp + 16) as u64, 4)
+    };
+    let end = sdt + read::<u32>(sdt + 4) as u64;
+    (sdt + 36..end)
+        .step_by(entry_size)
+        .map(|entry| {
+            if entry_size == 8 {
+                read::<u64>(entry)
+            } else {
+                read::<u32>(entry) as u64
+            }
+        })
+        .find(|&table| read::<[u8; 4]>(table) == *b"APIC")
+}
+
+// ACPI 5.2.12
+fn parse_madt(madt: u64) -> Madt {
+    let mut result = Madt {
+        local_apic: read::<u32>(madt + 36) as u64,
+        io_apics: [None; MAX_IO_APICS],
+        overrides: [None; NUM_IRQS],
+    };
+    let end = madt + read::<u32>(madt + 4) as u64;
+    let mut entry = madt + 44;
+    while entry + 2 <= end {
+        let length = read::<u8>(entry + 1) as u64;
+        if length < 2 {
+            break;
+        }
+        match read::<u8>(entry) {
+            1 => {
+                if let Some(slot) = result.io_apics.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(IoApic {
+                        address: read::<u32>(entry + 4) as u64,
+                        gsi_base: read::<u32>(entry + 8),
+                    });
+                }
+            }
+            2 => {
+                let source = read::<u8>(entry + 3) as usize;
+                if source < NUM_IRQS {
+                    result.overrides[source] = Some(SourceOverride {
+                        gsi: read::<u32>(entry + 4),
+                        flags: read::<u16>(entry + 8),
+                    });
+                }
+            }
+            5 => result.local_apic = read::<u64>(entry + 4),
+            _ => {}
+        }
+        entry += length;
+    }
+    result
+}
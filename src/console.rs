@@ -0,0 +1,396 @@
+// The console: where print!() output goes, and line-oriented input with echo and editing.
+//
+// print!() and println!() send their output to every sink chosen with set_output(): the VGA
+// buffer, COM1, and a function supplied by the program. Sending output to COM1 as well as the
+// screen lets headless runs, such as QEMU with -serial stdio, capture everything printed.
+//
+// A LineEditor turns keys into a line of text, echoing it to the VGA buffer where print!()
+// would write next. The other sinks get the same edits as ANSI escape sequences, which a
+// serial terminal understands. read_line() drives one with keys from the key queue; programs
+// that receive keys through a keyboard handler can feed a LineEditor themselves.
+//
+// The line is confined to the rest of the row on which editing begins, so that editing never
+// needs to scroll the screen.
+
+use core::fmt;
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::vga_buffer::{is_drawable, Writer, BUFFER_WIDTH, WRITER};
+use crate::{keyboard, serial, vga_buffer};
+
+/// The sinks that receive **print!()** output.
+#[derive(Copy, Clone, Debug)]
+pub struct ConsoleOutput {
+    pub vga: bool,
+    pub serial: bool,
+    /// A function called with each piece of formatted text.
+    pub custom: Option<fn(&str)>,
+}
+
+impl ConsoleOutput {
+    /// Output to the VGA buffer only. This is the default.
+    pub const VGA: ConsoleOutput = ConsoleOutput {
+        vga: true,
+        serial: false,
+        custom: None,
+    };
+
+    /// Output to COM1 only.
+    pub const SERIAL: ConsoleOutput = ConsoleOutput {
+        vga: false,
+        serial: true,
+        custom: None,
+    };
+
+    /// Output to both the VGA buffer and COM1.
+    pub const BOTH: ConsoleOutput = ConsoleOutput {
+        vga: true,
+        serial: true,
+        custom: None,
+    };
+
+    /// Output to the given function only.
+    pub const fn custom(sink: fn(&str)) -> ConsoleOutput {
+        ConsoleOutput {
+            vga: false,
+            serial: false,
+            custom: Some(sink),
+        }
+    }
+}
+
+impl Default for ConsoleOutput {
+    fn default() -> Self {
+        ConsoleOutput::VGA
+    }
+}
+
+static OUTPUT: Mutex<ConsoleOutput> = Mutex::new(ConsoleOutput::VGA);
+
+/// Chooses the sinks that receive **print!()** output from now on.
+pub fn set_output(output: ConsoleOutput) {
+    without_interrupts(|| *OUTPUT.lock() = output);
+}
+
+/// Returns the sinks that currently receive **print!()** output.
+pub fn output() -> ConsoleOutput {
+    without_interrupts(|| *OUTPUT.lock())
+}
+
+struct CustomSink(fn(&str));
+
+impl fmt::Write for CustomSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+// An ANSI escape sequence that moves the cursor the given number of columns left (**'D'**) or
+// right (**'C'**). Moving zero columns writes nothing.
+struct CursorMove(usize, char);
+
+impl fmt::Display for CursorMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            0 => Ok(()),
+            n => write!(f, "\x1b[{}{}", n, self.1),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let output = output();
+    if output.vga {
+        vga_buffer::_print(args);
+    }
+    print_beyond_vga(output, args);
+}
+
+// Sends output to every sink other than the VGA buffer.
+fn print_beyond_vga(output: ConsoleOutput, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if output.serial {
+        serial::_print(args);
+    }
+    if let Some(sink) = output.custom {
+        let _ = CustomSink(sink).write_fmt(args);
+    }
+}
+
+/// The longest line a LineEditor can hold.
+pub const MAX_LINE_LEN: usize = BUFFER_WIDTH - 1;
+
+/// The number of previous lines that ArrowUp and ArrowDown can recall.
+pub const HISTORY_SIZE: usize = 8;
+
+const BACKSPACE: char = '\u{8}';
+const DELETE: char = '\u{7f}';
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    lines: [[0; MAX_LINE_LEN]; HISTORY_SIZE],
+    lens: [0; HISTORY_SIZE],
+    count: 0,
+});
+
+// The lines most recently entered, shared by every LineEditor.
+struct History {
+    lines: [[u8; MAX_LINE_LEN]; HISTORY_SIZE],
+    lens: [usize; HISTORY_SIZE],
+    // Total number of lines ever added; the most recent HISTORY_SIZE of them are kept.
+    count: usize,
+}
+
+impl History {
+    fn len(&self) -> usize {
+        self.count.min(HISTORY_SIZE)
+    }
+
+    // Entry 0 is the most recent line.
+    fn get(&self, age: usize) -> &[u8] {
+        let slot = (self.count - 1 - age) % HISTORY_SIZE;
+        &self.lines[slot][..self.lens[slot]]
+    }
+
+    fn add(&mut self, line: &[u8]) {
+        if line.is_empty() || (self.len() > 0 && self.get(0) == line) {
+            return;
+        }
+        let slot = self.count % HISTORY_SIZE;
+        self.lines[slot][..line.len()].copy_from_slice(line);
+        self.lens[slot] = line.len();
+        self.count += 1;
+    }
+}
+
+/// Collects keys into a line of text, echoing it to every **print!()** sink and supporting
+/// these edits:
+/// - Backspace and Delete remove the character before or under the cursor.
+/// - ArrowLeft, ArrowRight, Home and End move the cursor.
+/// - Insert switches between inserting and overwriting characters.
+/// - ArrowUp and ArrowDown step through previously entered lines.
+/// - Enter completes the line.
+///
+/// Only drawable ASCII characters are added to the line; other keys are ignored. Sinks other
+/// than the VGA buffer receive the edits as ANSI escape sequences that move the cursor and
+/// erase the rest of the line.
+pub struct LineEditor {
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+    cursor: usize,
+    capacity: usize,
+    max_len: usize,
+    // Column and row of the first character, once editing has begun.
+    start: Option<(usize, usize)>,
+    overwrite: bool,
+    // How far back in the history the line came from; 0 is the line being typed.
+    history_position: usize,
+    // Where the cursor was left in the echo to the sinks other than the VGA buffer.
+    echoed_cursor: usize,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    /// Creates an editor for lines of up to **MAX_LINE_LEN** characters.
+    pub const fn new() -> Self {
+        Self::with_max_len(MAX_LINE_LEN)
+    }
+
+    /// Creates an editor for lines of up to **max_len** characters, or **MAX_LINE_LEN** if that
+    /// is smaller.
+    pub const fn with_max_len(max_len: usize) -> Self {
+        LineEditor {
+            line: [0; MAX_LINE_LEN],
+            len: 0,
+            cursor: 0,
+            capacity: 0,
+            max_len: if max_len < MAX_LINE_LEN {
+                max_len
+            } else {
+                MAX_LINE_LEN
+            },
+            start: None,
+            overwrite: false,
+            history_position: 0,
+            echoed_cursor: 0,
+        }
+    }
+
+    /// Starts editing a new line at the current output position, moving the cursor there.
+    /// The first call to **key()** does this automatically, so calling it is only necessary to
+    /// show the cursor before the first key arrives.
+    pub fn begin(&mut self) {
+        without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            if writer.position().0 + 1 >= BUFFER_WIDTH {
+                writer.write_byte(b'\n');
+            }
+            let start = writer.position();
+            self.start = Some(start);
+            self.capacity = self.max_len.min(BUFFER_WIDTH - 1 - start.0);
+            self.len = 0;
+            self.cursor = 0;
+            self.history_position = 0;
+            self.echoed_cursor = 0;
+            self.redraw(&mut writer, 0);
+        });
+    }
+
+    /// Handles one key. Once Enter completes the line, echoes a newline, adds the line to the
+    /// history and returns it; the next key then begins a new line.
+    pub fn key(&mut self, key: DecodedKey) -> Option<&[u8]> {
+        if self.start.is_none() {
+            self.begin();
+        }
+        let old_len = self.len;
+        match key {
+            DecodedKey::Unicode('\n') | DecodedKey::Unicode('\r') => return Some(self.finish()),
+            DecodedKey::Unicode(BACKSPACE) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.remove();
+                }
+            }
+            DecodedKey::Unicode(DELETE) | DecodedKey::RawKey(KeyCode::Delete) => {
+                if self.cursor < self.len {
+                    self.remove();
+                }
+            }
+            DecodedKey::Unicode(c) => {
+                if c.is_ascii() && is_drawable(c) {
+                    self.insert(c as u8);
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                self.cursor = (self.cursor + 1).min(self.len)
+            }
+            DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+            DecodedKey::RawKey(KeyCode::End) => self.cursor = self.len,
+            DecodedKey::RawKey(KeyCode::Insert) => self.overwrite = !self.overwrite,
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.recall(self.history_position + 1),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                self.recall(self.history_position.saturating_sub(1))
+            }
+            DecodedKey::RawKey(_) => {}
+        }
+        without_interrupts(|| self.redraw(&mut WRITER.lock(), old_len));
+        self.echo_beyond_vga();
+        None
+    }
+
+    /// Returns the line as edited so far.
+    pub fn line(&self) -> &[u8] {
+        &self.line[..self.len]
+    }
+
+    fn insert(&mut self, byte: u8) {
+        if self.overwrite && self.cursor < self.len {
+            self.line[self.cursor] = byte;
+        } else if self.len < self.capacity {
+            self.line
+                .copy_within(self.cursor..self.len, self.cursor + 1);
+            self.line[self.cursor] = byte;
+            self.len += 1;
+        } else {
+            return;
+        }
+        self.cursor += 1;
+    }
+
+    fn remove(&mut self) {
+        self.line
+            .copy_within(self.cursor + 1..self.len, self.cursor);
+        self.len -= 1;
+    }
+
+    // Replaces the line with the given history entry, where 0 means an empty line.
+    fn recall(&mut self, position: usize) {
+        without_interrupts(|| {
+            let history = HISTORY.lock();
+            if position > history.len() {
+                return;
+            }
+            let entry = match position {
+                0 => &[],
+                _ => history.get(position - 1),
+            };
+            self.len = entry.len().min(self.capacity);
+            self.line[..self.len].copy_from_slice(&entry[..self.len]);
+            self.cursor = self.len;
+            self.history_position = position;
+        });
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        let (col, row) = self.start.take().unwrap_or_default();
+        without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            writer.set_position(col + self.len, row);
+            writer.write_byte(b'\n');
+            HISTORY.lock().add(self.line());
+        });
+        let end = CursorMove(self.len - self.echoed_cursor, 'C');
+        print_beyond_vga(output(), format_args!("{}\n", end));
+        self.line()
+    }
+
+    // Rewrites the line on the sinks other than the VGA buffer: back to its start, the whole
+    // line, erase whatever an older, longer line left behind, then back to the cursor. The
+    // output may change while a line is being edited, so **echoed_cursor** follows the cursor
+    // even when there is nowhere to echo; **finish()** relies on it never passing the end of
+    // the line.
+    fn echo_beyond_vga(&mut self) {
+        let output = output();
+        let echoed_cursor = core::mem::replace(&mut self.echoed_cursor, self.cursor);
+        if !output.serial && output.custom.is_none() {
+            return;
+        }
+        let line = core::str::from_utf8(self.line()).unwrap_or("");
+        print_beyond_vga(
+            output,
+            format_args!(
+                "{}{}\x1b[K{}",
+                CursorMove(echoed_cursor, 'D'),
+                line,
+                CursorMove(self.len - self.cursor, 'D')
+            ),
+        );
+    }
+
+    // Draws the line, blanking anything left over from a line of length **old_len**, and moves
+    // the hardware cursor to the editing position.
+    fn redraw(&self, writer: &mut Writer, old_len: usize) {
+        let (col, row) = self.start.unwrap_or_default();
+        for i in 0..old_len.max(self.len) {
+            let byte = if i < self.len { self.line[i] } else { b' ' };
+            writer.plot_byte(col + i, row, byte);
+        }
+        writer.set_position(col + self.cursor, row);
+    }
+}
+
+/// Reads a line of text into **buf**, echoing it and allowing the edits described under
+/// **LineEditor**, and returns its length. The line can be no longer than **buf**, nor longer
+/// than the space remaining in the current row of the screen.
+///
+/// Keys come from the key queue, so the same restrictions apply as for
+/// **keyboard::read_key()**, including its **panic** if key queueing is not enabled.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut editor = LineEditor::with_max_len(buf.len());
+    editor.begin();
+    loop {
+        if let Some(line) = editor.key(keyboard::read_key()) {
+            buf[..line.len()].copy_from_slice(line);
+            return line.len();
+        }
+    }
+}
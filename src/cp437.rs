@@ -0,0 +1,55 @@
+// Translation between Unicode and code page 437, the character set built into the VGA font.
+//
+// Besides ASCII, code page 437 has accented letters, Greek letters, math symbols, box-drawing
+// and block characters, and a set of symbols such as card suits and arrows in place of the
+// ASCII control characters.
+
+// The Unicode character shown for each byte. 0x00 is left as NUL, and the control characters
+// are listed as the symbols the font draws for them.
+const CP437: [char; 256] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼', //
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/', //
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?', //
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', //
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_', //
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', //
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂', //
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// Characters that look the same as one in the table, and are just as likely to be typed.
+// U+2126 is the OHM SIGN, which looks like the table's Greek capital omega.
+const ALIASES: [(char, u8); 3] = [('β', 0xE1), ('μ', 0xE6), ('\u{2126}', 0xEA)];
+
+/// Returns the code page 437 byte that draws **c**, or **None** if the VGA font has no such
+/// character. ASCII control characters have no byte, even though the font draws symbols for
+/// their codes; use the symbols themselves, such as '♥', to draw those.
+pub fn from_char(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '\0'..='\u{7f}' => None,
+        _ => CP437
+            .iter()
+            .position(|&entry| entry == c)
+            .map(|byte| byte as u8)
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == c)
+                    .map(|(_, byte)| *byte)
+            }),
+    }
+}
+
+/// Returns the Unicode character that code page 437 byte **byte** draws.
+pub fn to_char(byte: u8) -> char {
+    CP437[byte as usize]
+}
@@ -0,0 +1,250 @@
+// Handlers for the CPU exceptions that interrupts.rs does not handle itself. Without them,
+// any of these exceptions escalates into a double fault, which hides the original cause.
+// Each handler calls the user's hook from the HandlerTable if there is one; otherwise, it
+// reports what went wrong and panics. Handlers that can return mark themselves as running in
+// interrupt context, so that hooks cannot wait for interrupts.
+
+use core::fmt;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode,
+};
+
+use crate::interrupts::InterruptContext;
+use crate::println;
+
+/// User-supplied exception handlers. A hook that returns resumes execution at the faulting
+/// instruction.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct ExceptionHooks {
+    pub(crate) breakpoint: Option<fn(&InterruptStackFrame)>,
+    pub(crate) divide_error: Option<fn(&InterruptStackFrame)>,
+    pub(crate) invalid_opcode: Option<fn(&InterruptStackFrame)>,
+    pub(crate) stack_segment_fault: Option<fn(&InterruptStackFrame, u64)>,
+    pub(crate) general_protection: Option<fn(&InterruptStackFrame, u64)>,
+    pub(crate) page_fault: Option<fn(&InterruptStackFrame, PageFaultErrorCode)>,
+    pub(crate) alignment_check: Option<fn(&InterruptStackFrame, u64)>,
+}
+
+lazy_static! {
+    static ref HOOKS: Mutex<ExceptionHooks> = Mutex::new(ExceptionHooks::default());
+}
+
+pub(crate) fn set_hooks(hooks: ExceptionHooks) {
+    *HOOKS.lock() = hooks;
+}
+
+// Copies the hooks out, so that the lock is not held while a hook runs.
+pub(crate) fn hooks() -> ExceptionHooks {
+    *HOOKS.lock()
+}
+
+/// Installs the exception handlers in **idt**.
+pub(crate) fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.non_maskable_interrupt
+        .set_handler_fn(non_maskable_interrupt_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+}
+
+fn fault(name: &str, stack_frame: &InterruptStackFrame, diagnosis: fmt::Arguments) -> ! {
+    panic!("EXCEPTION: {}\n{}\n{:#?}", name, diagnosis, stack_frame);
+}
+
+struct Selector(u64);
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match SelectorErrorCode::new(self.0) {
+            Some(code) if !code.is_null() => write!(f, "{:?}", code),
+            _ => write!(f, "{:#x} (not caused by a segment selector)", self.0),
+        }
+    }
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().divide_error {
+        return hook(&stack_frame);
+    }
+    fault(
+        "DIVIDE ERROR",
+        &stack_frame,
+        format_args!("Division by zero, or a quotient too large for its destination."),
+    );
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    println!("EXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    fault(
+        "OVERFLOW",
+        &stack_frame,
+        format_args!("INTO executed while the overflow flag was set."),
+    );
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    fault(
+        "BOUND RANGE EXCEEDED",
+        &stack_frame,
+        format_args!("BOUND found an index outside of its array bounds."),
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().invalid_opcode {
+        return hook(&stack_frame);
+    }
+    fault(
+        "INVALID OPCODE",
+        &stack_frame,
+        format_args!(
+            "The instruction at {:?} is undefined or not supported by this CPU.",
+            stack_frame.instruction_pointer
+        ),
+    );
+}
+
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    fault(
+        "DEVICE NOT AVAILABLE",
+        &stack_frame,
+        format_args!("A floating-point or SIMD instruction ran while the FPU was disabled."),
+    );
+}
+
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    fault(
+        "INVALID TSS",
+        &stack_frame,
+        format_args!("Error code: {}", Selector(error_code)),
+    );
+}
+
+extern "x86-interrupt" fn segment_not_present_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fault(
+        "SEGMENT NOT PRESENT",
+        &stack_frame,
+        format_args!("Error code: {}", Selector(error_code)),
+    );
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().stack_segment_fault {
+        return hook(&stack_frame, error_code);
+    }
+    fault(
+        "STACK SEGMENT FAULT",
+        &stack_frame,
+        format_args!(
+            "The stack limit was exceeded or a bad stack segment was loaded.\nError code: {}",
+            Selector(error_code)
+        ),
+    );
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().general_protection {
+        return hook(&stack_frame, error_code);
+    }
+    fault(
+        "GENERAL PROTECTION FAULT",
+        &stack_frame,
+        format_args!("Error code: {}", Selector(error_code)),
+    );
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().page_fault {
+        return hook(&stack_frame, error_code);
+    }
+    fault(
+        "PAGE FAULT",
+        &stack_frame,
+        format_args!(
+            "Accessed address: {:#x}\nError code: {:?}",
+            Cr2::read_raw(),
+            error_code
+        ),
+    );
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    fault(
+        "x87 FLOATING POINT",
+        &stack_frame,
+        format_args!("An unmasked x87 floating-point exception occurred."),
+    );
+}
+
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _context = InterruptContext::enter();
+    if let Some(hook) = hooks().alignment_check {
+        return hook(&stack_frame, error_code);
+    }
+    fault(
+        "ALIGNMENT CHECK",
+        &stack_frame,
+        format_args!("Unaligned memory access while alignment checking was enabled."),
+    );
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    fault(
+        "MACHINE CHECK",
+        &stack_frame,
+        format_args!("The CPU detected an internal or bus error."),
+    );
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    fault(
+        "SIMD FLOATING POINT",
+        &stack_frame,
+        format_args!("An unmasked SSE floating-point exception occurred."),
+    );
+}
@@ -1,19 +1,33 @@
-use crate::HandlerTable;
-use crate::{gdt, println};
+use crate::InterruptHandlers;
+use crate::{apic, exceptions, gdt, keyboard, mouse, println, ps2, serial, time, timers};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
+use x86_64::instructions::port::Port;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
 // This code is largely Copyright (c) 2019 Philipp Oppermann.
 // Gabriel Ferrer added:
-// - HANDLERS variable.
-// - Use of HANDLERS in init_idt, timer_interrupt_handler, keyboard_interrupt_handler
+// - HANDLERS variable, holding the user's InterruptHandlers.
+// - Use of HANDLERS in set_handlers, idle, handlers_in_use, timer_interrupt_handler, keyboard_interrupt_handler
+// - wait_for_interrupt() and wait_until()
+// - Key queueing and raw key events in keyboard_interrupt_handler; the decoder is now in
+//   keyboard.rs
 // - enum WhichInterrupt and the variable to hold its value
+// - Tick counting and software timers in timer_interrupt_handler
+// - Installation of the handlers from exceptions.rs
+// - Handlers for IRQs 2-15, handle_irq(), and the IRQ mask functions
+// - Spurious IRQ detection
+// - end_of_interrupt() and the APIC spurious interrupt handler, to support the APIC backend
+// - Reading scancodes and updating the LEDs through ps2.rs in keyboard_interrupt_handler
+// - mouse_interrupt_handler
+// - serial_interrupt_handler
+// - InterruptContext and in_interrupt(), to track when an interrupt handler is running
 
 #[derive(Copy, Clone, Debug)]
 pub enum WhichInterrupt {
-    Timer, Keyboard, Breakpoint,
+    Timer, Keyboard, Mouse, Serial, Breakpoint, Irq(u8),
 }
 
 lazy_static! {
@@ -21,12 +35,13 @@ lazy_static! {
 }
 
 lazy_static! {
-    static ref HANDLERS: Mutex<Option<HandlerTable>> = Mutex::new(None);
+    static ref HANDLERS: Mutex<Option<&'static mut dyn InterruptHandlers>> = Mutex::new(None);
 }
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        exceptions::install(&mut idt);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         unsafe {
             idt.double_fault
@@ -35,18 +50,137 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        for (line, handler) in IRQ_HANDLERS {
+            idt[irq_vector(line)].set_handler_fn(handler);
+        }
+        idt[irq_vector(mouse::IRQ)].set_handler_fn(mouse_interrupt_handler);
+        idt[irq_vector(serial::COM1_IRQ)].set_handler_fn(serial_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR].set_handler_fn(apic_spurious_handler);
         idt
     };
 }
 
-/// Initializes the interrupt table with the given interrupt handlers.
-pub fn init_idt(handlers: HandlerTable) {
-    *(HANDLERS.lock()) = Some(handlers);
+static IDT_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the interrupt table.
+pub fn init_idt() {
     IDT.load();
+    IDT_LOADED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true once **init_idt()** has been called.
+pub fn idt_loaded() -> bool {
+    IDT_LOADED.load(Ordering::SeqCst)
+}
+
+/// Installs the interrupt handlers. Until this is called, interrupts are still serviced, but
+/// no user code is invoked.
+pub fn set_handlers(handlers: &'static mut dyn InterruptHandlers) {
+    *(HANDLERS.lock()) = Some(handlers);
+}
+
+/// Returns true while one of the user's InterruptHandlers methods is running.
+pub fn handlers_in_use() -> bool {
+    HANDLERS.is_locked()
+}
+
+static INTERRUPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks the code between **enter()** and the guard's drop as running in interrupt context.
+/// Every interrupt and exception handler that can return holds one of these, so that code
+/// it calls (software timers, exception hooks, user handlers) is recognized as such even
+/// when it runs without the HANDLERS lock.
+pub(crate) struct InterruptContext;
+
+impl InterruptContext {
+    pub(crate) fn enter() -> Self {
+        INTERRUPT_DEPTH.fetch_add(1, Ordering::SeqCst);
+        InterruptContext
+    }
+}
+
+impl Drop for InterruptContext {
+    fn drop(&mut self) {
+        INTERRUPT_DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns true while an interrupt or exception handler is running, including software timer
+/// callbacks and exception hooks.
+pub fn in_interrupt() -> bool {
+    INTERRUPT_DEPTH.load(Ordering::SeqCst) > 0
+}
+
+/// Halts the CPU until the next interrupt has been handled. This may be called from the cpu
+/// loop or from a startup handler. It will **panic** if called from within an interrupt
+/// handler, software timer callback, exception hook or idle handler, as no other interrupt
+/// can be serviced until such a handler returns.
+pub fn wait_for_interrupt() {
+    use x86_64::instructions::interrupts::{are_enabled, disable, enable_and_hlt};
+    check_can_wait();
+    if are_enabled() {
+        x86_64::instructions::hlt();
+    } else {
+        enable_and_hlt();
+        disable();
+    }
+}
+
+/// Halts the CPU in between interrupts until **poll** returns a value, then returns it. The
+/// same restrictions apply as for **wait_for_interrupt()**.
+///
+/// Unlike calling **wait_for_interrupt()** in a loop, **poll** runs with interrupts disabled
+/// and the CPU halts in the same instruction that enables them, so an interrupt that arrives
+/// just after **poll** gives up cannot be missed.
+pub fn wait_until<T, F: FnMut() -> Option<T>>(mut poll: F) -> T {
+    use x86_64::instructions::interrupts::{are_enabled, disable, enable, enable_and_hlt};
+    check_can_wait();
+    let were_enabled = are_enabled();
+    loop {
+        disable();
+        if let Some(value) = poll() {
+            if were_enabled {
+                enable();
+            }
+            return value;
+        }
+        enable_and_hlt();
+    }
+}
+
+fn check_can_wait() {
+    assert!(
+        !in_interrupt(),
+        "cannot wait for an interrupt inside an interrupt handler"
+    );
+    if !x86_64::instructions::interrupts::are_enabled() {
+        // Startup handlers run with interrupts disabled. It is safe to let interrupts through
+        // briefly as long as no user handler is running, because the interrupt would need that
+        // handler's lock.
+        assert!(
+            idt_loaded() && !handlers_in_use(),
+            "cannot wait before interrupts are initialized or inside an idle handler"
+        );
+    }
+}
+
+/// Runs the idle handler with interrupts disabled, so that it has exclusive access to the
+/// user's InterruptHandlers.
+pub fn idle() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let h = &mut *HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.on_idle();
+        }
+    });
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
     *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Breakpoint);
+    if let Some(hook) = exceptions::hooks().breakpoint {
+        return hook(&stack_frame);
+    }
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -77,47 +211,213 @@ impl InterruptIndex {
     }
 }
 
+/// The number of IRQ lines provided by the two PICs.
+pub const NUM_IRQS: u8 = 16;
+
+/// The IRQ line through which the secondary PIC signals the primary PIC.
+pub const CASCADE_IRQ: u8 = 2;
+
+pub(crate) fn irq_vector(line: u8) -> u8 {
+    PIC_1_OFFSET + line
+}
+
+/// Replaces the IRQ masks. Bit **n** of **mask** disables IRQ line **n** when set.
+pub fn set_irq_mask(mask: u16) {
+    if apic::is_enabled() {
+        apic::set_irq_mask(mask);
+    } else {
+        unsafe {
+            PICS.lock().write_masks(mask as u8, (mask >> 8) as u8);
+        }
+    }
+}
+
+/// Returns the current IRQ masks, in the format used by **set_irq_mask()**.
+pub fn irq_mask() -> u16 {
+    if apic::is_enabled() {
+        apic::irq_mask()
+    } else {
+        let masks = unsafe { PICS.lock().read_masks() };
+        masks[0] as u16 | (masks[1] as u16) << 8
+    }
+}
+
+fn end_of_interrupt(line: u8) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(irq_vector(line));
+        }
+    }
+}
+
+/// Enables the given IRQ line. It will **panic** if **line** is not below **NUM_IRQS**.
+pub fn enable_irq(line: u8) {
+    assert!(line < NUM_IRQS, "Undefined IRQ line: {}", line);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        set_irq_mask(irq_mask() & !(1 << line));
+    });
+}
+
+/// Disables the given IRQ line. It will **panic** if **line** is not below **NUM_IRQS**.
+pub fn disable_irq(line: u8) {
+    assert!(line < NUM_IRQS, "Undefined IRQ line: {}", line);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        set_irq_mask(irq_mask() | 1 << line);
+    });
+}
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+const READ_ISR: u8 = 0x0B;
+
+static SPURIOUS_IRQS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of spurious interrupts received on IRQ 7 or IRQ 15, or from the local
+/// APIC when using the APIC backend.
+pub fn spurious_irq_count() -> u64 {
+    SPURIOUS_IRQS.load(Ordering::SeqCst)
+}
+
+// Reads the In-Service Register of the PIC with the given command port.
+fn read_isr(command_port: u16) -> u8 {
+    let mut command: Port<u8> = Port::new(command_port);
+    unsafe {
+        command.write(READ_ISR);
+        command.read()
+    }
+}
+
+// A PIC signals IRQ 7 (or 15 on the secondary PIC) when a request disappears before it can
+// be delivered. Such an interrupt is not in service, so it must not receive an EOI from that
+// PIC. A spurious IRQ 15 does still count as a real interrupt on the primary PIC's cascade
+// line, so the primary PIC needs its EOI.
+fn is_spurious(line: u8) -> bool {
+    let spurious = match line {
+        7 => read_isr(PIC_1_COMMAND) & 0x80 == 0,
+        15 => read_isr(PIC_2_COMMAND) & 0x80 == 0,
+        _ => false,
+    };
+    if spurious {
+        SPURIOUS_IRQS.fetch_add(1, Ordering::SeqCst);
+        if line == 15 {
+            unsafe {
+                PICS.lock().notify_end_of_interrupt(irq_vector(CASCADE_IRQ));
+            }
+        }
+    }
+    spurious
+}
+
+// Spurious interrupts from the local APIC need no EOI.
+extern "x86-interrupt" fn apic_spurious_handler(_stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    SPURIOUS_IRQS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn handle_irq(line: u8) {
+    if !apic::is_enabled() && is_spurious(line) {
+        return;
+    }
+    *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Irq(line));
+    let h = &mut *HANDLERS.lock();
+    if let Some(handler) = h {
+        handler.on_irq(line);
+    }
+    end_of_interrupt(line);
+}
+
+// The x86-interrupt ABI does not tell a handler which vector invoked it, so each line needs a
+// handler of its own. IRQs 4 and 12 have dedicated handlers for COM1 and the mouse instead.
+macro_rules! irq_handlers {
+    ($($line:literal => $name:ident),*) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                let _context = InterruptContext::enter();
+                handle_irq($line);
+            }
+        )*
+
+        const IRQ_HANDLERS: [(u8, extern "x86-interrupt" fn(InterruptStackFrame)); 12] =
+            [$(($line, $name)),*];
+    };
+}
+
+irq_handlers!(
+    2 => irq2_handler, 3 => irq3_handler, 5 => irq5_handler, 6 => irq6_handler,
+    7 => irq7_handler, 8 => irq8_handler, 9 => irq9_handler, 10 => irq10_handler,
+    11 => irq11_handler, 13 => irq13_handler, 14 => irq14_handler, 15 => irq15_handler
+);
+
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
     *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Timer);
-    let h = &*HANDLERS.lock();
+    time::record_tick();
+    timers::fire_due();
+    let h = &mut *HANDLERS.lock();
     if let Some(handler) = h {
-        handler.handle_timer();
-    }
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        handler.on_timer();
     }
+    end_of_interrupt(InterruptIndex::Timer.as_u8() - PIC_1_OFFSET);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
     *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Keyboard);
-    use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
-    use x86_64::instructions::port::Port;
-
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
+    while let Some(scancode) = ps2::read_scancode() {
+        if let Some((event, modifiers, key)) = keyboard::decode(scancode) {
+            ps2::update_leds(&event, &modifiers);
+            let h = &mut *HANDLERS.lock();
+            if let Some(handler) = h {
+                handler.on_raw_key(event, modifiers);
+            }
+            if let Some(key) = key {
+                if keyboard::is_queueing() {
+                    keyboard::enqueue(key);
+                } else if let Some(handler) = h {
+                    handler.on_key(key);
+                }
+            }
+        }
     }
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    end_of_interrupt(InterruptIndex::Keyboard.as_u8() - PIC_1_OFFSET);
+}
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            let h = &*HANDLERS.lock();
+// Until the mouse is enabled, IRQ 12 is handled like any other line.
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    if !mouse::is_enabled() {
+        return handle_irq(mouse::IRQ);
+    }
+    *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Mouse);
+    while let Some(byte) = ps2::read_mouse_byte() {
+        if let Some(event) = mouse::receive(byte) {
+            let h = &mut *HANDLERS.lock();
             if let Some(handler) = h {
-                handler.handle_keyboard(key);
+                handler.on_mouse(event);
             }
         }
     }
+    end_of_interrupt(mouse::IRQ);
+}
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+// Until serial input is enabled, IRQ 4 is handled like any other line.
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _context = InterruptContext::enter();
+    if !serial::is_receiving() {
+        return handle_irq(serial::COM1_IRQ);
+    }
+    *(LAST_INTERRUPT.lock()) = Some(WhichInterrupt::Serial);
+    let h = &mut *HANDLERS.lock();
+    // Several bytes may have arrived before the interrupt was serviced.
+    while let Some(byte) = serial::receive() {
+        if serial::is_queueing() {
+            serial::enqueue(byte);
+        } else if let Some(handler) = h {
+            handler.on_serial(byte);
+        }
     }
+    end_of_interrupt(serial::COM1_IRQ);
 }
@@ -0,0 +1,243 @@
+// Keyboard decoding and keyboard state that can be read outside of interrupt handlers.
+//
+// When key queueing is enabled with HandlerTable::queue_keys(), the keyboard interrupt handler
+// only decodes keys and adds them to a queue; the program reads them whenever it is ready.
+//
+// The decoder also tracks which keys are currently held down, so that programs can poll the
+// keyboard rather than react to individual presses and releases.
+//
+// The layout, scancode set and control key handling are chosen through the HandlerTable and
+// can be changed at any time with configure().
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use pc_keyboard::layouts::{self, AnyLayout};
+use pc_keyboard::{
+    DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, Modifiers, ScancodeSet1,
+    ScancodeSet2,
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::interrupts;
+use crate::ring_buffer::RingBuffer;
+
+/// The number of keys the queue can hold. Keys typed while the queue is full are discarded.
+pub const KEY_QUEUE_SIZE: usize = 64;
+
+/// The keyboard layouts the decoder supports.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Us104Key,
+    Uk105Key,
+    De105Key,
+    Azerty,
+    Dvorak104Key,
+    DvorakProgrammer104Key,
+    Colemak,
+    Jis109Key,
+}
+
+impl Layout {
+    fn any_layout(self) -> AnyLayout {
+        match self {
+            Layout::Us104Key => AnyLayout::Us104Key(layouts::Us104Key),
+            Layout::Uk105Key => AnyLayout::Uk105Key(layouts::Uk105Key),
+            Layout::De105Key => AnyLayout::De105Key(layouts::De105Key),
+            Layout::Azerty => AnyLayout::Azerty(layouts::Azerty),
+            Layout::Dvorak104Key => AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+            Layout::DvorakProgrammer104Key => AnyLayout::DVP104Key(layouts::DVP104Key),
+            Layout::Colemak => AnyLayout::Colemak(layouts::Colemak),
+            Layout::Jis109Key => AnyLayout::Jis109Key(layouts::Jis109Key),
+        }
+    }
+}
+
+/// The scancode sets the decoder understands. By default, the keyboard controller translates
+/// whatever the keyboard sends into set 1. **HandlerTable::scancode_set()** and
+/// **ps2::set_scancode_set()** turn that translation off when set 2 is chosen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScancodeSetId {
+    Set1,
+    Set2,
+}
+
+/// Everything that determines how scancodes are decoded into keys.
+#[derive(Copy, Clone, Debug)]
+pub struct KeyboardConfig {
+    pub layout: Layout,
+    pub scancode_set: ScancodeSetId,
+    /// Whether Ctrl+letter combinations decode to control characters
+    /// (**HandleControl::MapLettersToUnicode**) or to plain letters (**HandleControl::Ignore**).
+    pub handle_control: HandleControl,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        KeyboardConfig {
+            layout: Layout::Us104Key,
+            scancode_set: ScancodeSetId::Set1,
+            handle_control: HandleControl::Ignore,
+        }
+    }
+}
+
+// Keyboard is generic over its scancode set, so each supported set needs its own variant.
+enum Decoder {
+    Set1(Keyboard<AnyLayout, ScancodeSet1>),
+    Set2(Keyboard<AnyLayout, ScancodeSet2>),
+}
+
+impl Decoder {
+    fn new(config: KeyboardConfig) -> Self {
+        let layout = config.layout.any_layout();
+        match config.scancode_set {
+            ScancodeSetId::Set1 => Decoder::Set1(Keyboard::new(
+                ScancodeSet1::new(),
+                layout,
+                config.handle_control,
+            )),
+            ScancodeSetId::Set2 => Decoder::Set2(Keyboard::new(
+                ScancodeSet2::new(),
+                layout,
+                config.handle_control,
+            )),
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        match self {
+            Decoder::Set1(keyboard) => keyboard.add_byte(byte),
+            Decoder::Set2(keyboard) => keyboard.add_byte(byte),
+        }
+        .ok()
+        .flatten()
+    }
+
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        match self {
+            Decoder::Set1(keyboard) => keyboard.process_keyevent(event),
+            Decoder::Set2(keyboard) => keyboard.process_keyevent(event),
+        }
+    }
+
+    fn get_modifiers(&self) -> &Modifiers {
+        match self {
+            Decoder::Set1(keyboard) => keyboard.get_modifiers(),
+            Decoder::Set2(keyboard) => keyboard.get_modifiers(),
+        }
+    }
+}
+
+struct KeyboardState {
+    config: KeyboardConfig,
+    decoder: Decoder,
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<KeyboardState> = Mutex::new(KeyboardState {
+        config: KeyboardConfig::default(),
+        decoder: Decoder::new(KeyboardConfig::default()),
+    });
+}
+
+// One bit per KeyCode, set while that key is held down.
+static PRESSED: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+static QUEUE_KEYS: AtomicBool = AtomicBool::new(false);
+static KEYS: RingBuffer<DecodedKey, KEY_QUEUE_SIZE> = RingBuffer::new(DecodedKey::Unicode('\0'));
+
+pub(crate) fn set_queueing(enabled: bool) {
+    QUEUE_KEYS.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns true if decoded keys go to the queue rather than to the keyboard handler.
+pub fn is_queueing() -> bool {
+    QUEUE_KEYS.load(Ordering::SeqCst)
+}
+
+/// Called by the keyboard interrupt handler with each byte read from the keyboard. Once the
+/// bytes add up to a key event, updates the key states and returns the event, the modifier
+/// state after the event, and the key it decodes to, if any.
+pub(crate) fn decode(scancode: u8) -> Option<(KeyEvent, Modifiers, Option<DecodedKey>)> {
+    let keyboard = &mut KEYBOARD.lock().decoder;
+    let event = keyboard.add_byte(scancode)?;
+    match event.state {
+        KeyState::Down => set_pressed(event.code, true),
+        KeyState::Up => set_pressed(event.code, false),
+        KeyState::SingleShot => {}
+    }
+    let key = keyboard.process_keyevent(event.clone());
+    Some((event, keyboard.get_modifiers().clone(), key))
+}
+
+fn set_pressed(code: KeyCode, pressed: bool) {
+    let index = code as usize;
+    let bit = 1 << (index % 64);
+    if pressed {
+        PRESSED[index / 64].fetch_or(bit, Ordering::SeqCst);
+    } else {
+        PRESSED[index / 64].fetch_and(!bit, Ordering::SeqCst);
+    }
+}
+
+/// Returns true if the given key is currently held down.
+pub fn is_pressed(code: KeyCode) -> bool {
+    let index = code as usize;
+    PRESSED[index / 64].load(Ordering::SeqCst) & 1 << (index % 64) != 0
+}
+
+/// Returns the current state of the modifier keys (Shift, Ctrl, Alt) and lock keys.
+pub fn modifiers() -> Modifiers {
+    without_interrupts(|| KEYBOARD.lock().decoder.get_modifiers().clone())
+}
+
+/// Switches the decoder to a new layout, scancode set or control key handling. Any key
+/// sequence in progress and the state of the modifier keys are reset.
+///
+/// This only affects the decoder. To change the scancode set the keyboard actually delivers,
+/// use **ps2::set_scancode_set()** instead.
+pub fn configure(config: KeyboardConfig) {
+    without_interrupts(|| {
+        *KEYBOARD.lock() = KeyboardState {
+            config,
+            decoder: Decoder::new(config),
+        };
+    });
+}
+
+/// Switches the decoder to a new layout, keeping the other settings.
+pub fn set_layout(layout: Layout) {
+    configure(KeyboardConfig { layout, ..config() });
+}
+
+/// Returns the current decoder settings.
+pub fn config() -> KeyboardConfig {
+    without_interrupts(|| KEYBOARD.lock().config)
+}
+
+/// Called by the keyboard interrupt handler.
+pub(crate) fn enqueue(key: DecodedKey) {
+    KEYS.push(key);
+}
+
+/// Removes and returns the oldest key in the queue, or **None** if no key is waiting.
+pub fn try_read_key() -> Option<DecodedKey> {
+    KEYS.pop()
+}
+
+/// Waits for a key to arrive in the queue, halting the CPU in between interrupts, then
+/// removes and returns it. The same restrictions apply as for **time::sleep_ticks()**.
+/// It will **panic** if key queueing is not enabled, as no key would ever arrive.
+pub fn read_key() -> DecodedKey {
+    assert!(
+        is_queueing(),
+        "read_key() requires HandlerTable::queue_keys()"
+    );
+    interrupts::wait_until(try_read_key)
+}
+
+/// Discards every key in the queue.
+pub fn clear_keys() {
+    KEYS.clear();
+}
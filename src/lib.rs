@@ -101,8 +101,7 @@
 //! For this program, I created a
 //! [GhostHunterGame struct](https://github.com/gjf2a/ghost_hunter_core/blob/master/src/lib.rs)
 //! to represent the state of the game. It is wrapped in a **Mutex** and initialized using
-//! [lazy_static!](https://docs.rs/lazy_static/1.4.0/lazy_static/) to ensure safe access. Nearly
-//! any nontrivial program will need to make use of this design pattern.
+//! [lazy_static!](https://docs.rs/lazy_static/1.4.0/lazy_static/) to ensure safe access.
 //!
 //! The **tick()** function calls a special
 //! [ghost_hunter::tick()](https://github.com/gjf2a/ghost_hunter/blob/master/src/lib.rs) function
@@ -112,6 +111,36 @@
 //! The **key()** function calls the **GhostHunterGame::key()** method to convey updates to game
 //! state resulting from keypresses.
 //!
+//! Programs with state can avoid the global **Mutex** altogether by implementing the
+//! **InterruptHandlers** trait and handing their state to **HandlerTable::start_with()**. The
+//! interrupt code takes ownership of the state and lends it to each handler in turn:
+//! ```
+//! #![no_std]
+//! #![no_main]
+//!
+//! use ghost_hunter_core::GhostHunterGame;
+//! use ghost_hunter::MainGame;
+//! use pluggable_interrupt_os::{HandlerTable, InterruptHandlers};
+//! use pc_keyboard::DecodedKey;
+//!
+//! struct Game(MainGame);
+//!
+//! impl InterruptHandlers for Game {
+//!     fn on_timer(&mut self) {
+//!         ghost_hunter::tick(&mut self.0);
+//!     }
+//!
+//!     fn on_key(&mut self, key: DecodedKey) {
+//!         self.0.key(key);
+//!     }
+//! }
+//!
+//! #[no_mangle]
+//! pub extern "C" fn _start() -> ! {
+//!     HandlerTable::new().start_with(Game(GhostHunterGame::new()))
+//! }
+//! ```
+//!
 //! This is a pedagogical experiment. I would be interested to hear from anyone who
 //! finds this useful or has suggestions.
 
@@ -121,14 +150,72 @@
 // hlt_loop() and panic() are Copyright (c) 2019 Philipp Oppermann.
 // Everything else is written by Gabriel Ferrer.
 
+pub mod apic;
+pub mod console;
+pub mod cp437;
+mod exceptions;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod mouse;
+pub mod pit;
+pub mod ps2;
+mod ring_buffer;
 pub mod serial;
+pub mod time;
+pub mod timers;
 pub mod vga_buffer;
 
 use core::panic::PanicInfo;
 
-use pc_keyboard::DecodedKey;
+use pc_keyboard::{DecodedKey, HandleControl, KeyEvent, Modifiers};
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+
+use crate::console::ConsoleOutput;
+use crate::exceptions::ExceptionHooks;
+use crate::keyboard::{KeyboardConfig, Layout, ScancodeSetId};
+use crate::mouse::MouseEvent;
+
+/// Interrupt handlers that carry their own state.
+///
+/// Every method has a default implementation that does nothing, so implementors only need to
+/// supply the handlers they care about. Pass an implementor to **HandlerTable::start_with()**;
+/// from then on the interrupt code owns it and calls these methods with interrupts disabled.
+///
+/// As support for more interrupts is added to this crate, each one gets a new method here with
+/// a do-nothing default, so existing implementations keep compiling.
+pub trait InterruptHandlers: Send {
+    /// Called once, before interrupts are enabled.
+    fn on_startup(&mut self) {}
+
+    /// Called on every timer interrupt.
+    fn on_timer(&mut self) {}
+
+    /// Called whenever a keypress is decoded into a [DecodedKey](https://docs.rs/pc-keyboard/0.8.0/pc_keyboard/enum.DecodedKey.html).
+    fn on_key(&mut self, _key: DecodedKey) {}
+
+    /// Called whenever a key is pressed or released, before any **on_key()** call for the same
+    /// event. **modifiers** reflects the state of Shift, Ctrl, Alt and the lock keys after the
+    /// event.
+    fn on_raw_key(&mut self, _event: KeyEvent, _modifiers: Modifiers) {}
+
+    /// Called on an interrupt from one of the PIC lines 2 through 15. Only lines enabled with
+    /// **HandlerTable::irq()** or **HandlerTable::enable_irq()** generate interrupts.
+    fn on_irq(&mut self, _line: u8) {}
+
+    /// Called whenever the mouse moves or a mouse button changes, once the mouse has been
+    /// enabled with **HandlerTable::enable_mouse()**.
+    fn on_mouse(&mut self, _event: MouseEvent) {}
+
+    /// Called with each byte received on COM1, once serial input has been enabled with
+    /// **HandlerTable::enable_serial()**.
+    fn on_serial(&mut self, _byte: u8) {}
+
+    /// Called by the default cpu loop each time the CPU wakes up from **hlt**, that is, after
+    /// each batch of interrupts has been handled. It is not called if a custom cpu loop was
+    /// installed with **HandlerTable::cpu_loop()**.
+    fn on_idle(&mut self) {}
+}
 
 /// Table of interrupt handlers. This struct uses the
 /// [Builder pattern](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
@@ -136,14 +223,38 @@ use pc_keyboard::DecodedKey;
 /// up the handlers. When ready, call the **.start()** method to start up your pluggable
 /// interrupt operating system.
 ///
-/// For now, it only includes timer and keyboard handlers.
+/// For now, it only includes timer, keyboard, raw keyboard, mouse, serial, startup, idle and
+/// IRQ handlers.
 /// I will add more if it seems useful to do so.
 /// Double-fault handling is addressed "behind the scenes".
+///
+/// CPU exceptions are reported "behind the scenes" as well, unless you supply your own handler
+/// for one of them.
+///
+/// Handlers that need to keep state between calls can implement **InterruptHandlers** instead
+/// and be started with **.start_with()**. In that case, this table only supplies configuration,
+/// such as the cpu loop and the exception handlers.
+#[derive(Clone)]
 pub struct HandlerTable {
     timer: Option<fn()>,
     keyboard: Option<fn(DecodedKey)>,
+    raw_keyboard: Option<fn(KeyEvent, Modifiers)>,
+    mouse: Option<fn(MouseEvent)>,
+    serial: Option<fn(u8)>,
     startup: Option<fn()>,
+    idle: Option<fn()>,
     cpu_loop: fn() -> !,
+    timer_hz: Option<u32>,
+    exceptions: ExceptionHooks,
+    irqs: [Option<fn()>; interrupts::NUM_IRQS as usize],
+    irq_mask: u16,
+    apic: Option<u64>,
+    queue_keys: bool,
+    keyboard_config: KeyboardConfig,
+    mouse_enabled: bool,
+    serial_enabled: bool,
+    queue_serial: bool,
+    console_output: ConsoleOutput,
 }
 
 impl HandlerTable {
@@ -152,16 +263,47 @@ impl HandlerTable {
         HandlerTable {
             timer: None,
             keyboard: None,
+            raw_keyboard: None,
+            mouse: None,
+            serial: None,
             startup: None,
-            cpu_loop: hlt_loop,
+            idle: None,
+            cpu_loop: idle_loop,
+            timer_hz: None,
+            exceptions: ExceptionHooks::default(),
+            irqs: [None; interrupts::NUM_IRQS as usize],
+            irq_mask: DEFAULT_IRQ_MASK,
+            apic: None,
+            queue_keys: false,
+            keyboard_config: KeyboardConfig::default(),
+            mouse_enabled: false,
+            serial_enabled: false,
+            queue_serial: false,
+            console_output: ConsoleOutput::VGA,
         }
     }
 
     /// Starts up a simple operating system using the specified handlers.
     pub fn start(self) -> ! {
-        self.startup.map(|f| f());
+        let handlers = self.clone();
+        self.start_with(handlers)
+    }
+
+    /// Starts up a simple operating system in which **handlers** receives every interrupt
+    /// event. Every handler function stored in this table is ignored: timer, keyboard, raw
+    /// keyboard, mouse, serial, IRQ, startup and idle. Only the cpu loop is used as usual.
+    /// The table's other settings, such as the timer frequency, enabled devices and exception
+    /// hooks, still apply.
+    pub fn start_with<H: InterruptHandlers + 'static>(self, mut handlers: H) -> ! {
+        init(&self);
+        handlers.on_startup();
         let fore = self.cpu_loop;
-        init(self);
+        // handlers lives in this stack frame, which never returns, so it remains valid for the
+        // rest of the program.
+        let handlers: &'static mut dyn InterruptHandlers =
+            unsafe { &mut *(&mut handlers as *mut H) };
+        interrupts::set_handlers(handlers);
+        x86_64::instructions::interrupts::enable();
         (fore)();
     }
 
@@ -172,6 +314,15 @@ impl HandlerTable {
         self
     }
 
+    /// Sets how many timer interrupts should occur per second. Without this setting, the timer
+    /// runs at its power-on rate of about 18.2 Hz. The hardware cannot go slower than that, and
+    /// it can only approximate most rates; **pit::frequency_hz()** reports the rate actually used.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn timer_hz(mut self, hz: u32) -> Self {
+        self.timer_hz = Some(hz);
+        self
+    }
+
     /// Called by the low-level interrupt routines to handle a timer event.
     pub fn handle_timer(&self) {
         if let Some(timer) = self.timer {
@@ -188,6 +339,62 @@ impl HandlerTable {
         self
     }
 
+    /// Sets the keyboard layout. The default is **Layout::Us104Key**. Use
+    /// **keyboard::set_layout()** to change it while running.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn keyboard_layout(mut self, layout: Layout) -> Self {
+        self.keyboard_config.layout = layout;
+        self
+    }
+
+    /// Sets the scancode set the keyboard decoder expects. The default is
+    /// **ScancodeSetId::Set1**, which is what the keyboard controller normally delivers. For
+    /// **ScancodeSetId::Set2**, the keyboard and controller are programmed at startup to
+    /// deliver set 2; startup will **panic** if they do not respond.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn scancode_set(mut self, scancode_set: ScancodeSetId) -> Self {
+        self.keyboard_config.scancode_set = scancode_set;
+        self
+    }
+
+    /// Sets whether Ctrl+letter combinations are decoded as control characters
+    /// (**HandleControl::MapLettersToUnicode**) or as plain letters (**HandleControl::Ignore**,
+    /// the default). [HandleControl](https://docs.rs/pc-keyboard/0.8.0/pc_keyboard/enum.HandleControl.html)
+    /// comes from the [pc_keyboard](https://crates.io/crates/pc-keyboard) crate.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn control_handling(mut self, handle_control: HandleControl) -> Self {
+        self.keyboard_config.handle_control = handle_control;
+        self
+    }
+
+    /// Sets the raw keyboard handler, which sees every key press and release, along with the
+    /// state of the modifier keys. The [KeyEvent](https://docs.rs/pc-keyboard/0.8.0/pc_keyboard/struct.KeyEvent.html)
+    /// and [Modifiers](https://docs.rs/pc-keyboard/0.8.0/pc_keyboard/struct.Modifiers.html)
+    /// types come from the [pc_keyboard](https://crates.io/crates/pc-keyboard) crate. To check
+    /// whether a key is being held down at any given time, use **keyboard::is_pressed()**.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn raw_keyboard(mut self, raw_keyboard_handler: fn(KeyEvent, Modifiers)) -> Self {
+        self.raw_keyboard = Some(raw_keyboard_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a key press or release.
+    pub fn handle_raw_keyboard(&self, event: KeyEvent, modifiers: Modifiers) {
+        if let Some(raw_keyboard) = self.raw_keyboard {
+            (raw_keyboard)(event, modifiers)
+        }
+    }
+
+    /// Sends decoded keys to a queue instead of the keyboard handler. The program can then
+    /// retrieve them outside of interrupt context with **keyboard::try_read_key()** or
+    /// **keyboard::read_key()**, typically from the cpu loop.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn queue_keys(mut self) -> Self {
+        self.queue_keys = true;
+        self
+    }
+
     /// Called by the low-level interrupt routines to handle a keyboard event.
     pub fn handle_keyboard(&self, key: DecodedKey) {
         if let Some(keyboard) = self.keyboard {
@@ -195,6 +402,75 @@ impl HandlerTable {
         }
     }
 
+    /// Sets the mouse handler, and enables the mouse. Each event reports the movement since the
+    /// last one, the state of the buttons, and where the mouse pointer is on the screen. The
+    /// pointer is only drawn after calling **mouse::show_pointer()**.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn mouse(mut self, mouse_handler: fn(MouseEvent)) -> Self {
+        self.mouse = Some(mouse_handler);
+        self.enable_mouse()
+    }
+
+    /// Enables the mouse without setting a handler for it. This is useful with
+    /// **.start_with()**, where **InterruptHandlers::on_mouse()** handles mouse events.
+    /// Startup will **panic** if no mouse responds.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn enable_mouse(mut self) -> Self {
+        self.mouse_enabled = true;
+        self.irq_mask &= !(1 << mouse::IRQ);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a mouse event.
+    pub fn handle_mouse(&self, event: MouseEvent) {
+        if let Some(mouse) = self.mouse {
+            (mouse)(event)
+        }
+    }
+
+    /// Sets the serial handler, which receives each byte that arrives on COM1, and enables
+    /// serial input. Under QEMU, running with **-serial stdio** sends what is typed in the host
+    /// terminal.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn serial(mut self, serial_handler: fn(u8)) -> Self {
+        self.serial = Some(serial_handler);
+        self.enable_serial()
+    }
+
+    /// Enables serial input without setting a handler for it. This is useful with
+    /// **.start_with()**, where **InterruptHandlers::on_serial()** handles received bytes.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn enable_serial(mut self) -> Self {
+        self.serial_enabled = true;
+        self.irq_mask &= !(1 << serial::COM1_IRQ);
+        self
+    }
+
+    /// Enables serial input, sending received bytes to a queue instead of the serial handler.
+    /// The program can then retrieve them with **serial::try_read_byte()** or
+    /// **serial::read_byte()**.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn queue_serial(mut self) -> Self {
+        self.queue_serial = true;
+        self.enable_serial()
+    }
+
+    /// Chooses where **print!()** and **println!()** send their output. The default is
+    /// **ConsoleOutput::VGA**; **ConsoleOutput::BOTH** also sends it to COM1, so that it can be
+    /// captured from a headless run. Use **console::set_output()** to change it while running.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn console_output(mut self, output: ConsoleOutput) -> Self {
+        self.console_output = output;
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a byte received on COM1.
+    pub fn handle_serial(&self, byte: u8) {
+        if let Some(serial) = self.serial {
+            (serial)(byte)
+        }
+    }
+
     /// Sets the startup handler.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn startup(mut self, startup_handler: fn()) -> Self {
@@ -202,6 +478,131 @@ impl HandlerTable {
         self
     }
 
+    /// Called by the startup routine before interrupts are enabled.
+    pub fn handle_startup(&self) {
+        if let Some(startup) = self.startup {
+            (startup)()
+        }
+    }
+
+    /// Sets the idle handler, called each time the CPU wakes up from **hlt** in the default
+    /// cpu loop. It is not called if a custom cpu loop is set.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn idle(mut self, idle_handler: fn()) -> Self {
+        self.idle = Some(idle_handler);
+        self
+    }
+
+    /// Called by the default cpu loop whenever the CPU wakes up.
+    pub fn handle_idle(&self) {
+        if let Some(idle) = self.idle {
+            (idle)()
+        }
+    }
+
+    /// Sets the handler for one of the PIC lines 3 through 15, and enables that line. Lines 0
+    /// and 1 are the timer and keyboard, and line 2 connects the two PICs, so it will **panic**
+    /// given any of those or a line above 15.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn irq(mut self, line: u8, irq_handler: fn()) -> Self {
+        self = self.enable_irq(line);
+        self.irqs[line as usize] = Some(irq_handler);
+        self
+    }
+
+    /// Enables one of the PIC lines 3 through 15 without setting a handler for it. This is
+    /// useful with **.start_with()**, where **InterruptHandlers::on_irq()** handles the line.
+    /// It will **panic** under the same conditions as **.irq()**.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn enable_irq(mut self, line: u8) -> Self {
+        assert!(
+            line > interrupts::CASCADE_IRQ && line < interrupts::NUM_IRQS,
+            "IRQ line {} cannot be assigned a handler",
+            line
+        );
+        self.irq_mask &= !(1 << line);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle an interrupt on the given IRQ line.
+    pub fn handle_irq(&self, line: u8) {
+        if let Some(irq) = self.irqs.get(line as usize).copied().flatten() {
+            (irq)()
+        }
+    }
+
+    /// Delivers interrupts through the local APIC and I/O APIC instead of the legacy 8259
+    /// PICs. The timer, keyboard and IRQ handlers work exactly as they do with the PICs; the
+    /// local APIC timer takes over from the PIT, running at the rate set by **.timer_hz()**.
+    ///
+    /// The APIC registers and ACPI tables must be reachable, so the bootloader has to map all
+    /// of physical memory. Enable the **map_physical_memory** feature of the **bootloader**
+    /// crate, declare the entry point with **bootloader::entry_point!**, and pass
+    /// **boot_info.physical_memory_offset** here. Startup will **panic** if no APIC is found.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn apic(mut self, physical_memory_offset: u64) -> Self {
+        self.apic = Some(physical_memory_offset);
+        self
+    }
+
+    /// Sets the breakpoint handler, replacing the default handler that prints the stack frame.
+    /// Execution continues after the **int3** instruction when the handler returns.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn breakpoint(mut self, handler: fn(&InterruptStackFrame)) -> Self {
+        self.exceptions.breakpoint = Some(handler);
+        self
+    }
+
+    /// Sets the divide error handler, replacing the default handler that panics.
+    ///
+    /// When this handler or any of the other fault handlers below returns, the CPU retries the
+    /// faulting instruction. Unless the handler has removed the cause of the fault, it should
+    /// not return.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn divide_error(mut self, handler: fn(&InterruptStackFrame)) -> Self {
+        self.exceptions.divide_error = Some(handler);
+        self
+    }
+
+    /// Sets the invalid opcode handler, replacing the default handler that panics.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn invalid_opcode(mut self, handler: fn(&InterruptStackFrame)) -> Self {
+        self.exceptions.invalid_opcode = Some(handler);
+        self
+    }
+
+    /// Sets the stack segment fault handler, replacing the default handler that panics.
+    /// The handler also receives the error code pushed by the CPU.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn stack_segment_fault(mut self, handler: fn(&InterruptStackFrame, u64)) -> Self {
+        self.exceptions.stack_segment_fault = Some(handler);
+        self
+    }
+
+    /// Sets the general protection fault handler, replacing the default handler that panics.
+    /// The handler also receives the error code pushed by the CPU.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn general_protection(mut self, handler: fn(&InterruptStackFrame, u64)) -> Self {
+        self.exceptions.general_protection = Some(handler);
+        self
+    }
+
+    /// Sets the page fault handler, replacing the default handler that panics. The accessed
+    /// address is available from **x86_64::registers::control::Cr2::read()**.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn page_fault(mut self, handler: fn(&InterruptStackFrame, PageFaultErrorCode)) -> Self {
+        self.exceptions.page_fault = Some(handler);
+        self
+    }
+
+    /// Sets the alignment check handler, replacing the default handler that panics.
+    /// The handler also receives the error code pushed by the CPU.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn alignment_check(mut self, handler: fn(&InterruptStackFrame, u64)) -> Self {
+        self.exceptions.alignment_check = Some(handler);
+        self
+    }
+
     /// Sets the cpu loop handler.
     /// This function should contain an infinite loop.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
@@ -211,11 +612,77 @@ impl HandlerTable {
     }
 }
 
-fn init(handlers: HandlerTable) {
+impl InterruptHandlers for HandlerTable {
+    fn on_startup(&mut self) {
+        self.handle_startup();
+    }
+
+    fn on_timer(&mut self) {
+        self.handle_timer();
+    }
+
+    fn on_key(&mut self, key: DecodedKey) {
+        self.handle_keyboard(key);
+    }
+
+    fn on_raw_key(&mut self, event: KeyEvent, modifiers: Modifiers) {
+        self.handle_raw_keyboard(event, modifiers);
+    }
+
+    fn on_irq(&mut self, line: u8) {
+        self.handle_irq(line);
+    }
+
+    fn on_mouse(&mut self, event: MouseEvent) {
+        self.handle_mouse(event);
+    }
+
+    fn on_serial(&mut self, byte: u8) {
+        self.handle_serial(byte);
+    }
+
+    fn on_idle(&mut self) {
+        self.handle_idle();
+    }
+}
+
+// Only the timer, the keyboard and the link between the PICs are enabled by default.
+const DEFAULT_IRQ_MASK: u16 = !0b111;
+
+// Interrupts remain disabled until the handlers are in place, but everything they need is
+// ready, so that the startup handler can sleep.
+fn init(table: &HandlerTable) {
+    console::set_output(table.console_output);
     gdt::init();
-    interrupts::init_idt(handlers);
+    exceptions::set_hooks(table.exceptions);
+    keyboard::set_queueing(table.queue_keys);
+    keyboard::configure(table.keyboard_config);
+    if table.serial_enabled {
+        serial::init_receive(table.queue_serial);
+    }
+    interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
-    x86_64::instructions::interrupts::enable();
+    match table.apic {
+        Some(physical_memory_offset) => {
+            apic::init(physical_memory_offset, table.timer_hz, table.irq_mask);
+        }
+        None => {
+            interrupts::set_irq_mask(table.irq_mask);
+            if let Some(hz) = table.timer_hz {
+                pit::set_frequency(hz);
+            }
+        }
+    }
+    if table.keyboard_config.scancode_set == ScancodeSetId::Set2 {
+        if let Err(e) = ps2::set_scancode_set(ScancodeSetId::Set2) {
+            panic!("Could not switch the keyboard to scancode set 2: {:?}", e);
+        }
+    }
+    if table.mouse_enabled {
+        if let Err(e) = mouse::init() {
+            panic!("Could not enable the mouse: {:?}", e);
+        }
+    }
 }
 
 fn hlt_loop() -> ! {
@@ -224,6 +691,13 @@ fn hlt_loop() -> ! {
     }
 }
 
+fn idle_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+        interrupts::idle();
+    }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
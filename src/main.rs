@@ -1,11 +1,8 @@
 #![no_std]
 #![no_main]
 
-mod serial;
-mod vga_buffer;
-
 use pc_keyboard::DecodedKey;
-use pluggable_interrupt_os::HandlerTable;
+use pluggable_interrupt_os::{print, println, HandlerTable};
 use no_panic::no_panic;
 
 #[no_panic]
@@ -0,0 +1,258 @@
+// PS/2 mouse support.
+//
+// The mouse sends a packet of 3 bytes (4 if it has a scroll wheel) for every change in its
+// position or buttons. The mouse interrupt handler hands each byte to receive(), which
+// assembles packets and decodes them into MouseEvents.
+//
+// The module also tracks a pointer position in text cells and, if asked, shows the pointer by
+// swapping the foreground and background colors of the character beneath it.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::ps2::{self, Ps2Error};
+use crate::vga_buffer::{ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+
+/// The IRQ line of the PS/2 mouse.
+pub const IRQ: u8 = 12;
+
+/// How far the mouse must move, in its own units, to move the pointer by one column.
+pub const UNITS_PER_COL: i32 = 8;
+/// How far the mouse must move, in its own units, to move the pointer by one row.
+pub const UNITS_PER_ROW: i32 = 16;
+
+// Bits of the first byte of a packet.
+const LEFT_BUTTON: u8 = 0x01;
+const RIGHT_BUTTON: u8 = 0x02;
+const MIDDLE_BUTTON: u8 = 0x04;
+const ALWAYS_SET: u8 = 0x08;
+const X_SIGN: u8 = 0x10;
+const Y_SIGN: u8 = 0x20;
+const X_OVERFLOW: u8 = 0x40;
+const Y_OVERFLOW: u8 = 0x80;
+
+const MAX_PACKET_SIZE: usize = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PACKET_SIZE: AtomicUsize = AtomicUsize::new(3);
+static PACKET: Mutex<Packet> = Mutex::new(Packet {
+    bytes: [0; MAX_PACKET_SIZE],
+    len: 0,
+});
+static POINTER: Mutex<Pointer> = Mutex::new(Pointer {
+    x: BUFFER_WIDTH as i32 * UNITS_PER_COL / 2,
+    y: BUFFER_HEIGHT as i32 * UNITS_PER_ROW / 2,
+    visible: false,
+    drawn: None,
+});
+
+/// The state of the mouse buttons.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// A change in the position or buttons of the mouse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Horizontal movement; positive values are to the right.
+    pub dx: i16,
+    /// Vertical movement; positive values are downward, matching the order of rows on screen.
+    pub dy: i16,
+    /// Scroll wheel movement; positive values are toward the user. Always 0 for a mouse
+    /// without a wheel.
+    pub wheel: i8,
+    /// The buttons held down after this event.
+    pub buttons: MouseButtons,
+    /// The column of the pointer after this event.
+    pub col: usize,
+    /// The row of the pointer after this event.
+    pub row: usize,
+}
+
+struct Packet {
+    bytes: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+struct Pointer {
+    // Position in mouse units, kept within the screen.
+    x: i32,
+    y: i32,
+    visible: bool,
+    // Where the pointer is drawn, and what it looks like there.
+    drawn: Option<(usize, usize, ScreenChar)>,
+}
+
+impl Pointer {
+    fn cell(&self) -> (usize, usize) {
+        (
+            (self.x / UNITS_PER_COL) as usize,
+            (self.y / UNITS_PER_ROW) as usize,
+        )
+    }
+
+    fn move_by(&mut self, dx: i16, dy: i16) {
+        self.x = (self.x + dx as i32).clamp(0, BUFFER_WIDTH as i32 * UNITS_PER_COL - 1);
+        self.y = (self.y + dy as i32).clamp(0, BUFFER_HEIGHT as i32 * UNITS_PER_ROW - 1);
+    }
+
+    // Restores the character beneath the pointer, unless something else has been drawn there
+    // in the meantime.
+    fn erase(&mut self, writer: &mut Writer) {
+        if let Some((col, row, drawn)) = self.drawn.take() {
+            if writer.peek(col, row) == drawn {
+                writer.plot(col, row, drawn.inverted());
+            }
+        }
+    }
+
+    fn redraw(&mut self, writer: &mut Writer) {
+        self.erase(writer);
+        if self.visible {
+            let (col, row) = self.cell();
+            let drawn = writer.peek(col, row).inverted();
+            writer.plot(col, row, drawn);
+            self.drawn = Some((col, row, drawn));
+        }
+    }
+}
+
+/// Called by the writer to scroll the VGA buffer. The pointer is erased first and drawn again
+/// afterwards, so that its inverted cell does not scroll away with the text. If the pointer is
+/// being updated by the code this interrupted, it is left alone rather than waited for.
+pub(crate) fn around_scroll(writer: &mut Writer, scroll: fn(&mut Writer)) {
+    match POINTER.try_lock() {
+        Some(mut pointer) => {
+            pointer.erase(writer);
+            scroll(writer);
+            pointer.redraw(writer);
+        }
+        None => scroll(writer),
+    }
+}
+
+/// Enables the mouse. Called during startup if **HandlerTable::mouse()** or
+/// **HandlerTable::enable_mouse()** was used.
+pub(crate) fn init() -> Result<(), Ps2Error> {
+    let wheel = ps2::enable_mouse()?;
+    PACKET_SIZE.store(if wheel { 4 } else { 3 }, Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if the mouse has been enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Returns true if the mouse has a scroll wheel.
+pub fn has_wheel() -> bool {
+    PACKET_SIZE.load(Ordering::SeqCst) == MAX_PACKET_SIZE
+}
+
+/// Called by the mouse interrupt handler with each byte from the mouse. Once a packet is
+/// complete, moves the pointer and returns the event it describes.
+pub(crate) fn receive(byte: u8) -> Option<MouseEvent> {
+    let mut packet = PACKET.lock();
+    // Every first byte has this bit set. Skipping bytes until one does lets the decoder
+    // recover if it ever gets out of step with the mouse.
+    if packet.len == 0 && byte & ALWAYS_SET == 0 {
+        return None;
+    }
+    let len = packet.len;
+    packet.bytes[len] = byte;
+    packet.len += 1;
+    if packet.len < PACKET_SIZE.load(Ordering::SeqCst) {
+        return None;
+    }
+    packet.len = 0;
+    let mut event = decode(&packet.bytes);
+
+    let mut pointer = POINTER.lock();
+    pointer.move_by(event.dx, event.dy);
+    (event.col, event.row) = pointer.cell();
+    // Waiting for the VGA buffer here could deadlock with the code this interrupted, so the
+    // pointer stays put until the next event if the buffer is in use.
+    if let Some(mut writer) = WRITER.try_lock() {
+        pointer.redraw(&mut writer);
+    }
+    Some(event)
+}
+
+fn decode(bytes: &[u8; MAX_PACKET_SIZE]) -> MouseEvent {
+    let flags = bytes[0];
+    let movement = |value: u8, sign: u8, overflow: u8| {
+        if flags & overflow != 0 {
+            0
+        } else if flags & sign != 0 {
+            value as i16 - 0x100
+        } else {
+            value as i16
+        }
+    };
+    MouseEvent {
+        dx: movement(bytes[1], X_SIGN, X_OVERFLOW),
+        // The mouse counts upward movement as positive.
+        dy: -movement(bytes[2], Y_SIGN, Y_OVERFLOW),
+        // The wheel movement is a 4-bit signed number.
+        wheel: if has_wheel() {
+            (bytes[3] << 4) as i8 >> 4
+        } else {
+            0
+        },
+        buttons: MouseButtons {
+            left: flags & LEFT_BUTTON != 0,
+            right: flags & RIGHT_BUTTON != 0,
+            middle: flags & MIDDLE_BUTTON != 0,
+        },
+        col: 0,
+        row: 0,
+    }
+}
+
+/// Returns the (column, row) of the pointer.
+pub fn pointer_position() -> (usize, usize) {
+    without_interrupts(|| POINTER.lock().cell())
+}
+
+/// Moves the pointer to the given column and row. It will **panic** if the position is
+/// outside the screen.
+pub fn set_pointer_position(col: usize, row: usize) {
+    assert!(
+        col < BUFFER_WIDTH && row < BUFFER_HEIGHT,
+        "Pointer position ({}, {}) is off the screen",
+        col,
+        row
+    );
+    update_pointer(|pointer| {
+        pointer.x = col as i32 * UNITS_PER_COL;
+        pointer.y = row as i32 * UNITS_PER_ROW;
+    });
+}
+
+/// Shows the pointer, drawn by swapping the colors of the character beneath it.
+pub fn show_pointer() {
+    update_pointer(|pointer| pointer.visible = true);
+}
+
+/// Hides the pointer, restoring the character beneath it.
+pub fn hide_pointer() {
+    update_pointer(|pointer| pointer.visible = false);
+}
+
+/// Returns true if the pointer is shown.
+pub fn pointer_visible() -> bool {
+    without_interrupts(|| POINTER.lock().visible)
+}
+
+fn update_pointer(update: impl FnOnce(&mut Pointer)) {
+    without_interrupts(|| {
+        let mut pointer = POINTER.lock();
+        update(&mut pointer);
+        pointer.redraw(&mut WRITER.lock());
+    });
+}
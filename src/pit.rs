@@ -0,0 +1,126 @@
+// Driver for the 8253/8254 Programmable Interval Timer (PIT). Channel 0 generates the timer
+// interrupt. Channel 2 serves as a stopwatch for calibrating other timers.
+//
+// The tick conversions below are based on the channel 0 divisor. When another timer drives
+// the timer interrupt, it is programmed to fire at the same rate as channel 0 would.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator that drives the PIT, in Hz.
+pub const BASE_HZ: u32 = 1_193_182;
+
+/// Largest divisor the PIT supports. It is also the divisor in effect at power-on, yielding
+/// roughly 18.2 timer interrupts per second.
+pub const MAX_DIVISOR: u32 = 0x10000;
+
+/// Smallest divisor the PIT supports in the square wave mode used for the timer interrupt.
+pub const MIN_DIVISOR: u32 = 2;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+const CHANNEL_2_GATE_PORT: u16 = 0x61;
+
+// Channel 0, low byte then high byte, mode 3 (square wave), binary counting.
+const CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+// Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary counting.
+const CHANNEL_2_ONE_SHOT: u8 = 0xB0;
+
+// Bits of the channel 2 gate port.
+const GATE: u8 = 0x01;
+const SPEAKER: u8 = 0x02;
+const OUTPUT: u8 = 0x20;
+
+static DIVISOR: AtomicU32 = AtomicU32::new(MAX_DIVISOR);
+
+/// Programs channel 0 to fire as close to **hz** times per second as the hardware allows.
+/// Returns the effective frequency, rounded to the nearest Hz.
+///
+/// Requests below roughly 19 Hz are clamped to the slowest rate the PIT supports, and requests
+/// above **BASE_HZ** / **MIN_DIVISOR** to the fastest.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = divisor_for(hz);
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL_0_PORT);
+    // A divisor of 0x10000 is written as 0, which the PIT interprets as 65536.
+    unsafe {
+        command.write(CHANNEL_0_SQUARE_WAVE);
+        data.write((divisor & 0xFF) as u8);
+        data.write(((divisor >> 8) & 0xFF) as u8);
+    }
+    set_tick_divisor(divisor);
+    frequency_hz()
+}
+
+/// Returns the channel 0 divisor that comes closest to **hz** timer interrupts per second.
+pub(crate) fn divisor_for(hz: u32) -> u32 {
+    let hz = hz.max(1);
+    ((BASE_HZ + hz / 2) / hz).clamp(MIN_DIVISOR, MAX_DIVISOR)
+}
+
+/// Records the number of PIT cycles between timer interrupts, for use by the conversions
+/// below, without reprogramming channel 0.
+pub(crate) fn set_tick_divisor(divisor: u32) {
+    DIVISOR.store(divisor, Ordering::SeqCst);
+}
+
+/// Returns the number of PIT cycles between timer interrupts.
+pub(crate) fn tick_divisor() -> u32 {
+    DIVISOR.load(Ordering::SeqCst)
+}
+
+/// Starts channel 2 counting down **count** PIT cycles, with the speaker disconnected.
+pub(crate) fn start_stopwatch(count: u16) {
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE_PORT);
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL_2_PORT);
+    unsafe {
+        let value = gate.read() & !(GATE | SPEAKER);
+        gate.write(value);
+        command.write(CHANNEL_2_ONE_SHOT);
+        data.write((count & 0xFF) as u8);
+        data.write((count >> 8) as u8);
+        // Counting starts on the rising edge of the gate.
+        gate.write(value | GATE);
+    }
+}
+
+/// Returns true once the count started by **start_stopwatch()** has reached zero.
+pub(crate) fn stopwatch_done() -> bool {
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE_PORT);
+    unsafe { gate.read() & OUTPUT != 0 }
+}
+
+/// Returns the effective timer interrupt frequency, rounded to the nearest Hz.
+pub fn frequency_hz() -> u32 {
+    let divisor = tick_divisor();
+    (BASE_HZ + divisor / 2) / divisor
+}
+
+/// Converts a number of timer interrupts into milliseconds, using the exact PIT divisor
+/// rather than the rounded frequency.
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    let divisor = tick_divisor() as u128;
+    (ticks as u128 * divisor * 1000 / BASE_HZ as u128) as u64
+}
+
+/// Converts a number of timer interrupts into nanoseconds.
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    let divisor = tick_divisor() as u128;
+    (ticks as u128 * divisor * 1_000_000_000 / BASE_HZ as u128) as u64
+}
+
+/// Converts a number of nanoseconds into the number of timer interrupts that span at least
+/// that long.
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    let divisor = tick_divisor() as u128;
+    (ns as u128 * BASE_HZ as u128).div_ceil(divisor * 1_000_000_000) as u64
+}
+
+/// Converts a number of milliseconds into the number of timer interrupts that span at least
+/// that long.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    let divisor = tick_divisor() as u128;
+    (ms as u128 * BASE_HZ as u128).div_ceil(divisor * 1000) as u64
+}
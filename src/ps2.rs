@@ -0,0 +1,404 @@
+// Driver for the 8042 PS/2 controller and the keyboard attached to its first port.
+//
+// The keyboard interrupt handler reads scancodes through this module. Every other operation
+// polls the controller with interrupts disabled, so that the interrupt handler cannot consume
+// the responses it is waiting for. Polling is timed with the PIT's stopwatch channel, so an
+// absent or unresponsive controller produces Ps2Error::Timeout rather than a hang.
+//
+// The keyboard LEDs are kept in sync with the decoder: whenever Caps Lock, Num Lock or
+// Scroll Lock is pressed, the keyboard interrupt handler calls update_leds(). It allows the
+// keyboard only a few milliseconds to respond, and drops the update if it does not.
+//
+// The keyboard itself always sends scancode set 2. The controller normally translates it into
+// set 1; set_scancode_set() turns that translation off when the decoder expects set 2.
+//
+// The mouse, if enabled, is attached to the second port. Its bytes arrive through the same
+// data port as the keyboard's, and the status register tells them apart. A byte from one
+// device that turns up while waiting for the other is set aside rather than dropped, and the
+// interrupt handlers collect set-aside bytes before reading the data port.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use pc_keyboard::{KeyCode, KeyEvent, KeyState, Modifiers};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::keyboard::{self, KeyboardConfig, ScancodeSetId};
+use crate::pit;
+use crate::ring_buffer::RingBuffer;
+
+const DATA_PORT: u16 = 0x60;
+// Reading this port yields the status register; writing it sends a controller command.
+const STATUS_PORT: u16 = 0x64;
+
+// Bits of the status register.
+const OUTPUT_FULL: u8 = 0x01;
+const INPUT_FULL: u8 = 0x02;
+const MOUSE_DATA: u8 = 0x20;
+
+// Bits of the controller configuration byte.
+const SECOND_PORT_INTERRUPT: u8 = 0x02;
+const SECOND_PORT_CLOCK_DISABLED: u8 = 0x20;
+const FIRST_PORT_TRANSLATION: u8 = 0x40;
+
+// Controller commands.
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const SELF_TEST: u8 = 0xAA;
+const TEST_FIRST_PORT: u8 = 0xAB;
+const ENABLE_SECOND_PORT: u8 = 0xA8;
+const WRITE_SECOND_PORT: u8 = 0xD4;
+
+// Keyboard commands.
+const SET_LEDS: u8 = 0xED;
+const SET_TYPEMATIC: u8 = 0xF3;
+const SET_SCANCODE_SET: u8 = 0xF0;
+const RESET: u8 = 0xFF;
+
+// Mouse commands.
+const GET_DEVICE_ID: u8 = 0xF2;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+
+// Responses.
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+const RESET_PASSED: u8 = 0xAA;
+const WHEEL_MOUSE_ID: u8 = 0x03;
+
+const TIMEOUT_MS: u32 = 50;
+// Every interrupt waits while the keyboard interrupt handler updates the LEDs, so it gives an
+// unresponsive keyboard only long enough to transfer a byte or two.
+const LED_UPDATE_TIMEOUT_MS: u32 = 3;
+// The keyboard runs its own self-test after a reset, which takes considerably longer.
+const RESET_TIMEOUT_MS: u32 = 1000;
+const MAX_RESENDS: usize = 3;
+const SET_ASIDE_SIZE: usize = 16;
+
+static SCROLL_LOCK: AtomicBool = AtomicBool::new(false);
+
+// Bytes that arrived for one device while this module was waiting for the other. Both are
+// only pushed and popped with interrupts disabled.
+static KEYBOARD_BYTES: RingBuffer<u8, SET_ASIDE_SIZE> = RingBuffer::new(0);
+static MOUSE_BYTES: RingBuffer<u8, SET_ASIDE_SIZE> = RingBuffer::new(0);
+
+/// Ways in which a conversation with the controller or keyboard can fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller did not accept or produce a byte in time.
+    Timeout,
+    /// The keyboard kept asking for a command to be sent again.
+    Resend,
+    /// The keyboard replied with something other than an acknowledgement.
+    UnexpectedResponse(u8),
+    /// The controller's self-test failed with the given result.
+    SelfTestFailed(u8),
+    /// The test of the keyboard port failed with the given result.
+    PortTestFailed(u8),
+}
+
+/// The state of the keyboard's three LEDs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    fn bits(self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+/// How long a key must be held before it starts repeating.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RepeatDelay {
+    Ms250,
+    Ms500,
+    Ms750,
+    Ms1000,
+}
+
+// Responses to controller commands arrive as if from the keyboard.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Device {
+    Keyboard,
+    Mouse,
+}
+
+impl Device {
+    fn set_aside(self) -> &'static RingBuffer<u8, SET_ASIDE_SIZE> {
+        match self {
+            Device::Keyboard => &KEYBOARD_BYTES,
+            Device::Mouse => &MOUSE_BYTES,
+        }
+    }
+}
+
+fn status() -> u8 {
+    let mut port: Port<u8> = Port::new(STATUS_PORT);
+    unsafe { port.read() }
+}
+
+// Returns the device that sent the waiting byte, if there is one.
+fn source(status: u8) -> Option<Device> {
+    if status & OUTPUT_FULL == 0 {
+        None
+    } else if status & MOUSE_DATA != 0 {
+        Some(Device::Mouse)
+    } else {
+        Some(Device::Keyboard)
+    }
+}
+
+fn read_data() -> u8 {
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    unsafe { port.read() }
+}
+
+// Polls the status register until **ready** accepts it, giving up after **ms** milliseconds.
+fn wait_for(ms: u32, mut ready: impl FnMut(u8) -> bool) -> Result<(), Ps2Error> {
+    for _ in 0..ms {
+        pit::start_stopwatch((pit::BASE_HZ / 1000) as u16);
+        while !pit::stopwatch_done() {
+            if ready(status()) {
+                return Ok(());
+            }
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn write(port: u16, byte: u8) -> Result<(), Ps2Error> {
+    write_within(TIMEOUT_MS, port, byte)
+}
+
+fn write_within(ms: u32, port: u16, byte: u8) -> Result<(), Ps2Error> {
+    wait_for(ms, |status| status & INPUT_FULL == 0)?;
+    let mut port: Port<u8> = Port::new(port);
+    unsafe { port.write(byte) };
+    Ok(())
+}
+
+// Reads the next byte from **device**, setting aside any bytes from the other device that
+// arrive first.
+fn read(device: Device, ms: u32) -> Result<u8, Ps2Error> {
+    let mut byte = None;
+    wait_for(ms, |status| match source(status) {
+        Some(from) if from == device => {
+            byte = Some(read_data());
+            true
+        }
+        Some(other) => {
+            other.set_aside().push(read_data());
+            false
+        }
+        None => false,
+    })?;
+    byte.ok_or(Ps2Error::Timeout)
+}
+
+// Moves anything left over in the output buffer aside, so that it is not mistaken for a
+// response. Nothing is lost: the interrupt handlers still receive it.
+fn flush() {
+    while let Some(device) = source(status()) {
+        device.set_aside().push(read_data());
+    }
+}
+
+// Sends **byte** and waits for its acknowledgement, giving up on each step after **ms**
+// milliseconds.
+fn send(device: Device, byte: u8, ms: u32) -> Result<(), Ps2Error> {
+    for _ in 0..MAX_RESENDS {
+        if let Device::Mouse = device {
+            write_within(ms, STATUS_PORT, WRITE_SECOND_PORT)?;
+        }
+        write_within(ms, DATA_PORT, byte)?;
+        match read(device, ms)? {
+            ACK => return Ok(()),
+            RESEND => {}
+            other => return Err(Ps2Error::UnexpectedResponse(other)),
+        }
+    }
+    Err(Ps2Error::Resend)
+}
+
+// Sends a command and its argument bytes to the keyboard, each of which must be acknowledged
+// within **ms** milliseconds.
+fn keyboard_command(bytes: &[u8], ms: u32) -> Result<(), Ps2Error> {
+    without_interrupts(|| {
+        flush();
+        bytes
+            .iter()
+            .try_for_each(|byte| send(Device::Keyboard, *byte, ms))
+    })
+}
+
+// Returns the oldest byte set aside for the given device, or else the waiting byte if it came
+// from that device.
+fn read_from(device: Device) -> Option<u8> {
+    device.set_aside().pop().or_else(|| {
+        if source(status()) == Some(device) {
+            Some(read_data())
+        } else {
+            None
+        }
+    })
+}
+
+/// Called repeatedly by the keyboard interrupt handler until it returns **None**, as bytes
+/// set aside during a command may be waiting along with the byte that raised the interrupt.
+pub(crate) fn read_scancode() -> Option<u8> {
+    read_from(Device::Keyboard)
+}
+
+/// Called repeatedly by the mouse interrupt handler until it returns **None**.
+pub(crate) fn read_mouse_byte() -> Option<u8> {
+    read_from(Device::Mouse)
+}
+
+/// Enables the second port and the mouse attached to it, so that it reports movement through
+/// IRQ 12. Returns true if the mouse has a scroll wheel, in which case it sends 4-byte packets
+/// rather than 3-byte packets.
+pub(crate) fn enable_mouse() -> Result<bool, Ps2Error> {
+    without_interrupts(|| {
+        flush();
+        write(STATUS_PORT, ENABLE_SECOND_PORT)?;
+        write(STATUS_PORT, READ_CONFIG)?;
+        let config = read(Device::Keyboard, TIMEOUT_MS)?;
+        write(STATUS_PORT, WRITE_CONFIG)?;
+        write(
+            DATA_PORT,
+            (config | SECOND_PORT_INTERRUPT) & !SECOND_PORT_CLOCK_DISABLED,
+        )?;
+        send(Device::Mouse, SET_DEFAULTS, TIMEOUT_MS)?;
+        // This sequence of sample rates asks a mouse with a scroll wheel to report it.
+        for rate in [200, 100, 80] {
+            send(Device::Mouse, SET_SAMPLE_RATE, TIMEOUT_MS)?;
+            send(Device::Mouse, rate, TIMEOUT_MS)?;
+        }
+        send(Device::Mouse, GET_DEVICE_ID, TIMEOUT_MS)?;
+        let id = read(Device::Mouse, TIMEOUT_MS)?;
+        send(Device::Mouse, ENABLE_REPORTING, TIMEOUT_MS)?;
+        Ok(id == WHEEL_MOUSE_ID)
+    })
+}
+
+/// Called by the keyboard interrupt handler with each key event, to update the LEDs when a
+/// lock key is pressed.
+pub(crate) fn update_leds(event: &KeyEvent, modifiers: &Modifiers) {
+    if event.state != KeyState::Down {
+        return;
+    }
+    match event.code {
+        KeyCode::ScrollLock => {
+            SCROLL_LOCK.fetch_xor(true, Ordering::SeqCst);
+        }
+        KeyCode::CapsLock | KeyCode::NumpadLock => {}
+        _ => return,
+    }
+    // A keyboard that fails to update its LEDs still works, so the update is simply dropped.
+    let _ = keyboard_command(
+        &[SET_LEDS, leds_for(modifiers).bits()],
+        LED_UPDATE_TIMEOUT_MS,
+    );
+}
+
+fn leds_for(modifiers: &Modifiers) -> Leds {
+    Leds {
+        scroll_lock: scroll_lock(),
+        num_lock: modifiers.numlock,
+        caps_lock: modifiers.capslock,
+    }
+}
+
+/// Returns true if Scroll Lock is on. The keyboard decoder does not track Scroll Lock, so
+/// this module does it instead.
+pub fn scroll_lock() -> bool {
+    SCROLL_LOCK.load(Ordering::SeqCst)
+}
+
+/// Lights the keyboard LEDs as given. The next press of a lock key restores the LEDs to match
+/// the decoder; use **sync_leds()** to do so immediately.
+pub fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    keyboard_command(&[SET_LEDS, leds.bits()], TIMEOUT_MS)
+}
+
+/// Lights the keyboard LEDs to match the decoder's Caps Lock and Num Lock state and the
+/// Scroll Lock state.
+pub fn sync_leds() -> Result<(), Ps2Error> {
+    set_leds(leds_for(&keyboard::modifiers()))
+}
+
+/// Sets how long a key must be held before it repeats, and how quickly it then repeats.
+/// **rate** ranges from 0 (about 30 repeats per second) to 31 (about 2 repeats per second).
+/// It will **panic** if **rate** is above 31.
+pub fn set_typematic(delay: RepeatDelay, rate: u8) -> Result<(), Ps2Error> {
+    assert!(rate < 32, "Typematic rate out of range: {}", rate);
+    keyboard_command(&[SET_TYPEMATIC, (delay as u8) << 5 | rate], TIMEOUT_MS)
+}
+
+/// Switches both the hardware and the decoder to the given scancode set. The keyboard is told
+/// to send set 2, and the controller's translation into set 1 is turned on for
+/// **ScancodeSetId::Set1** and off for **ScancodeSetId::Set2**. As with
+/// **keyboard::configure()**, any key sequence in progress and the state of the modifier keys
+/// are reset.
+pub fn set_scancode_set(scancode_set: ScancodeSetId) -> Result<(), Ps2Error> {
+    without_interrupts(|| {
+        keyboard_command(&[SET_SCANCODE_SET, 2], TIMEOUT_MS)?;
+        write(STATUS_PORT, READ_CONFIG)?;
+        let config = read(Device::Keyboard, TIMEOUT_MS)?;
+        let config = match scancode_set {
+            ScancodeSetId::Set1 => config | FIRST_PORT_TRANSLATION,
+            ScancodeSetId::Set2 => config & !FIRST_PORT_TRANSLATION,
+        };
+        write(STATUS_PORT, WRITE_CONFIG)?;
+        write(DATA_PORT, config)?;
+        keyboard::configure(KeyboardConfig {
+            scancode_set,
+            ..keyboard::config()
+        });
+        Ok(())
+    })
+}
+
+/// Runs the controller's self-test, then tests the keyboard port.
+pub fn self_test() -> Result<(), Ps2Error> {
+    without_interrupts(|| {
+        flush();
+        // Some controllers reset their configuration during the self-test.
+        write(STATUS_PORT, READ_CONFIG)?;
+        let config = read(Device::Keyboard, TIMEOUT_MS)?;
+        write(STATUS_PORT, SELF_TEST)?;
+        let result = read(Device::Keyboard, TIMEOUT_MS)?;
+        write(STATUS_PORT, WRITE_CONFIG)?;
+        write(DATA_PORT, config)?;
+        if result != SELF_TEST_PASSED {
+            return Err(Ps2Error::SelfTestFailed(result));
+        }
+        write(STATUS_PORT, TEST_FIRST_PORT)?;
+        match read(Device::Keyboard, TIMEOUT_MS)? {
+            PORT_TEST_PASSED => Ok(()),
+            result => Err(Ps2Error::PortTestFailed(result)),
+        }
+    })
+}
+
+/// Resets the keyboard and waits for it to pass its own self-test. This turns off its LEDs and
+/// restores its default repeat settings, so the decoder is reset as well, forgetting the state
+/// of the modifier and lock keys.
+pub fn reset_keyboard() -> Result<(), Ps2Error> {
+    without_interrupts(|| {
+        keyboard_command(&[RESET], TIMEOUT_MS)?;
+        let result = read(Device::Keyboard, RESET_TIMEOUT_MS)?;
+        keyboard::configure(keyboard::config());
+        SCROLL_LOCK.store(false, Ordering::SeqCst);
+        match result {
+            RESET_PASSED => Ok(()),
+            result => Err(Ps2Error::SelfTestFailed(result)),
+        }
+    })
+}
@@ -0,0 +1,63 @@
+// A fixed-size queue for passing values from an interrupt handler to the rest of the program
+// without a lock, so that the interrupt handler never has to wait. Code outside interrupt
+// handlers may push as well, as long as it does so with interrupts disabled.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub(crate) struct RingBuffer<T: Copy, const N: usize> {
+    items: UnsafeCell<[T; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// A push never overlaps another push or a pop: interrupt handlers do not interrupt one
+// another, and pushes outside interrupt handlers, like every pop, run with interrupts
+// disabled. So on a single CPU, at most one push or pop is ever in progress.
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// Creates an empty buffer. **filler** occupies the unused slots; it is never returned.
+    pub(crate) const fn new(filler: T) -> Self {
+        RingBuffer {
+            items: UnsafeCell::new([filler; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds **item** to the end of the buffer. Returns false, discarding **item**, if the
+    /// buffer is full. Only call this from an interrupt handler or with interrupts disabled.
+    pub(crate) fn push(&self, item: T) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return false;
+        }
+        unsafe {
+            (*self.items.get())[tail % N] = item;
+        }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Removes and returns the item at the front of the buffer, if any.
+    pub(crate) fn pop(&self) -> Option<T> {
+        without_interrupts(|| {
+            let head = self.head.load(Ordering::Relaxed);
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
+            let item = unsafe { (*self.items.get())[head % N] };
+            self.head.store(head.wrapping_add(1), Ordering::Release);
+            Some(item)
+        })
+    }
+
+    /// Discards everything in the buffer.
+    pub(crate) fn clear(&self) {
+        while self.pop().is_some() {}
+    }
+}
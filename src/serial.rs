@@ -1,24 +1,267 @@
-// All code in this file is Copyright (c) 2019 Philipp Oppermann.
+// Code in this file is largely Copyright (c) 2019 Philipp Oppermann.
+//
+// Gabriel Ferrer added:
+// - Receiving bytes from COM1: init_receive(), receive(), and the input queue
+// - SERIAL2 through SERIAL4, enum Com, configure(), and the serial_print_to! macros
 
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::interrupts;
+use crate::ring_buffer::RingBuffer;
+
+/// The IRQ line of COM1.
+pub const COM1_IRQ: u8 = 4;
+
+/// The IRQ line of COM2.
+pub const COM2_IRQ: u8 = 3;
+
+/// The number of received bytes the input queue can hold. Bytes received while the queue is
+/// full are discarded.
+pub const INPUT_QUEUE_SIZE: usize = 256;
+
+const COM1_PORT: u16 = 0x3F8;
+const COM2_PORT: u16 = 0x2F8;
+const COM3_PORT: u16 = 0x3E8;
+const COM4_PORT: u16 = 0x2E8;
+
+// Register offsets from a port's base address. The divisor registers replace the data and
+// interrupt enable registers while the divisor latch is open.
+const DIVISOR_LOW_OFFSET: u16 = 0;
+const DIVISOR_HIGH_OFFSET: u16 = 1;
+const LINE_CONTROL_OFFSET: u16 = 3;
+const LINE_STATUS_OFFSET: u16 = 5;
+
+const DATA_READY: u8 = 0x01;
+const DIVISOR_LATCH: u8 = 0x80;
+
+/// The rate of the UART's clock. Every baud rate is this divided by a whole number.
+pub const MAX_BAUD: u32 = 115_200;
+
+fn init_port(base: u16) -> Mutex<SerialPort> {
+    let mut serial_port = unsafe { SerialPort::new(base) };
+    serial_port.init();
+    Mutex::new(serial_port)
+}
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+    pub static ref SERIAL1: Mutex<SerialPort> = init_port(COM1_PORT);
+    pub static ref SERIAL2: Mutex<SerialPort> = init_port(COM2_PORT);
+    pub static ref SERIAL3: Mutex<SerialPort> = init_port(COM3_PORT);
+    pub static ref SERIAL4: Mutex<SerialPort> = init_port(COM4_PORT);
+}
+
+/// The four standard serial ports. Each is set to 38400 baud, 8 data bits, no parity and 1
+/// stop bit the first time it is used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Com {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl Com {
+    /// Returns the port's I/O base address.
+    pub fn base(self) -> u16 {
+        match self {
+            Com::Com1 => COM1_PORT,
+            Com::Com2 => COM2_PORT,
+            Com::Com3 => COM3_PORT,
+            Com::Com4 => COM4_PORT,
+        }
+    }
+
+    /// Returns the IRQ line the port conventionally uses. COM1 and COM3 share IRQ 4, and COM2
+    /// and COM4 share IRQ 3.
+    pub fn irq(self) -> u8 {
+        match self {
+            Com::Com1 | Com::Com3 => COM1_IRQ,
+            Com::Com2 | Com::Com4 => COM2_IRQ,
+        }
+    }
+
+    /// Returns the port, initializing it if this is its first use.
+    pub fn port(self) -> &'static Mutex<SerialPort> {
+        match self {
+            Com::Com1 => &SERIAL1,
+            Com::Com2 => &SERIAL2,
+            Com::Com3 => &SERIAL3,
+            Com::Com4 => &SERIAL4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// The parity bit is always 1.
+    Mark,
+    /// The parity bit is always 0.
+    Space,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// Two stop bits, or one and a half with **DataBits::Five**.
+    Two,
+}
+
+/// The line settings of a serial port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud: 38400,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl SerialConfig {
+    fn line_control(&self) -> u8 {
+        let parity = match self.parity {
+            Parity::None => 0x00,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18,
+            Parity::Mark => 0x28,
+            Parity::Space => 0x38,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0x00,
+            StopBits::Two => 0x04,
+        };
+        self.data_bits as u8 | stop_bits | parity
+    }
+}
+
+/// Changes the line settings of the given port. The device at the other end must use the
+/// same settings. It will **panic** if **config.baud** does not evenly divide **MAX_BAUD**.
+pub fn configure(com: Com, config: SerialConfig) {
+    assert!(
+        config.baud > 0 && MAX_BAUD.is_multiple_of(config.baud),
+        "Unsupported baud rate: {}",
+        config.baud
+    );
+    let divisor = (MAX_BAUD / config.baud) as u16;
+    let base = com.base();
+    let mut divisor_low: Port<u8> = Port::new(base + DIVISOR_LOW_OFFSET);
+    let mut divisor_high: Port<u8> = Port::new(base + DIVISOR_HIGH_OFFSET);
+    let mut line_control: Port<u8> = Port::new(base + LINE_CONTROL_OFFSET);
+    without_interrupts(|| {
+        // Holding the lock keeps anyone from printing while the divisor latch is open.
+        let _port = com.port().lock();
+        unsafe {
+            line_control.write(DIVISOR_LATCH);
+            divisor_low.write(divisor as u8);
+            divisor_high.write((divisor >> 8) as u8);
+            line_control.write(config.line_control());
+        }
+    });
+}
+
+static RECEIVING: AtomicBool = AtomicBool::new(false);
+static QUEUE_INPUT: AtomicBool = AtomicBool::new(false);
+static INPUT: RingBuffer<u8, INPUT_QUEUE_SIZE> = RingBuffer::new(0);
+
+/// Prepares COM1 to deliver received bytes through IRQ 4, either to the serial handler or to
+/// the input queue. Initializing the port enables its receive interrupt.
+pub(crate) fn init_receive(queue: bool) {
+    lazy_static::initialize(&SERIAL1);
+    QUEUE_INPUT.store(queue, Ordering::SeqCst);
+    RECEIVING.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if received bytes are being handled.
+pub fn is_receiving() -> bool {
+    RECEIVING.load(Ordering::SeqCst)
+}
+
+/// Returns true if received bytes go to the input queue rather than to the serial handler.
+pub fn is_queueing() -> bool {
+    QUEUE_INPUT.load(Ordering::SeqCst)
+}
+
+/// Called by the serial interrupt handler. Returns the next byte COM1 has received, if any.
+/// The port is read directly, as **SERIAL1** may be locked by the code that was interrupted.
+pub(crate) fn receive() -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(COM1_PORT + LINE_STATUS_OFFSET);
+    let mut data: Port<u8> = Port::new(COM1_PORT);
+    unsafe {
+        if line_status.read() & DATA_READY == 0 {
+            None
+        } else {
+            Some(data.read())
+        }
+    }
+}
+
+/// Called by the serial interrupt handler.
+pub(crate) fn enqueue(byte: u8) {
+    INPUT.push(byte);
+}
+
+/// Removes and returns the oldest byte in the input queue, or **None** if none is waiting.
+pub fn try_read_byte() -> Option<u8> {
+    INPUT.pop()
+}
+
+/// Waits for a byte to arrive in the input queue, halting the CPU in between interrupts, then
+/// removes and returns it. The same restrictions apply as for **time::sleep_ticks()**.
+/// It will **panic** if serial input queueing is not enabled, as no byte would ever arrive.
+pub fn read_byte() -> u8 {
+    assert!(
+        is_queueing(),
+        "read_byte() requires HandlerTable::queue_serial()"
+    );
+    interrupts::wait_until(try_read_byte)
+}
+
+/// Discards every byte in the input queue.
+pub fn clear_input() {
+    INPUT.clear();
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
+    _print_to(Com::Com1, args);
+}
+
+#[doc(hidden)]
+pub fn _print_to(com: Com, args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1
-        .lock()
-        .write_fmt(args)
-        .expect("Printing to serial failed");
+    // print!() may write here from an interrupt handler, so the port must not be held while
+    // interrupts are enabled.
+    without_interrupts(|| {
+        com.port()
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
 }
 
 /// Prints to the host through the serial interface.
@@ -37,3 +280,20 @@ macro_rules! serial_println {
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+/// Prints to the given serial port, such as **serial::Com::Com2**.
+#[macro_export]
+macro_rules! serial_print_to {
+    ($com:expr, $($arg:tt)*) => {
+        $crate::serial::_print_to($com, format_args!($($arg)*));
+    };
+}
+
+/// Prints to the given serial port, appending a newline.
+#[macro_export]
+macro_rules! serial_println_to {
+    ($com:expr) => ($crate::serial_print_to!($com, "\n"));
+    ($com:expr, $fmt:expr) => ($crate::serial_print_to!($com, concat!($fmt, "\n")));
+    ($com:expr, $fmt:expr, $($arg:tt)*) => ($crate::serial_print_to!(
+        $com, concat!($fmt, "\n"), $($arg)*));
+}
@@ -0,0 +1,151 @@
+// A monotonic clock driven by the timer interrupt. Every timer interrupt advances the tick
+// count by one; the pit module converts ticks into real time.
+
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{interrupts, pit};
+
+pub use core::time::Duration;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the timer interrupt handler.
+pub(crate) fn record_tick() {
+    TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Returns the number of timer interrupts since interrupts were enabled.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Returns the number of milliseconds since interrupts were enabled, at the resolution of the
+/// timer interrupt.
+pub fn uptime_ms() -> u64 {
+    pit::ticks_to_ms(ticks())
+}
+
+/// Returns the time since interrupts were enabled, at the resolution of the timer interrupt.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(pit::ticks_to_ns(ticks()))
+}
+
+/// Halts the CPU until at least **n** timer interrupts have occurred.
+///
+/// This may be called from the cpu loop or from a startup handler. It will **panic** if called
+/// from within an interrupt handler or idle handler, as the timer interrupt cannot be serviced
+/// until such a handler returns.
+pub fn sleep_ticks(n: u64) {
+    sleep_until(Instant(ticks().saturating_add(n)));
+}
+
+/// Halts the CPU for at least **ms** milliseconds. The same restrictions apply as for
+/// **sleep_ticks()**.
+pub fn sleep_ms(ms: u64) {
+    sleep_for_ticks(pit::ms_to_ticks(ms));
+}
+
+/// Halts the CPU for at least **duration**. The same restrictions apply as for
+/// **sleep_ticks()**.
+///
+/// It will **panic** if **duration** is too long to represent in timer interrupts.
+pub fn sleep(duration: Duration) {
+    let ns = u64::try_from(duration.as_nanos()).expect("sleep duration overflow");
+    sleep_for_ticks(pit::ns_to_ticks(ns));
+}
+
+// Part of the current tick has already elapsed when a sleep begins, so the next **n** timer
+// interrupts may span less than **n** full ticks. Waiting for one more guarantees that at
+// least **n** full ticks pass.
+fn sleep_for_ticks(n: u64) {
+    if n > 0 {
+        sleep_ticks(n.saturating_add(1));
+    }
+}
+
+/// Halts the CPU until **deadline** has passed. The same restrictions apply as for
+/// **sleep_ticks()**.
+///
+/// Instants only have the resolution of the timer interrupt, so a deadline of
+/// **Instant::now() + duration** may pass up to one tick before **duration** has elapsed.
+/// Use **sleep()** when the full duration must elapse.
+pub fn sleep_until(deadline: Instant) {
+    interrupts::wait_until(|| deadline.has_passed().then_some(()));
+}
+
+/// A moment in time, measured in timer interrupts since interrupts were enabled.
+///
+/// Conversions to and from **Duration** use the current timer frequency, so they are only
+/// meaningful if the frequency is not changed after startup.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current moment.
+    pub fn now() -> Self {
+        Instant(ticks())
+    }
+
+    /// Returns the moment at which the given number of timer interrupts had occurred.
+    pub fn from_ticks(ticks: u64) -> Self {
+        Instant(ticks)
+    }
+
+    /// Returns the number of timer interrupts that had occurred at this moment.
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed from **earlier** to this moment, or zero if **earlier** is
+    /// actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(pit::ticks_to_ns(self.0.saturating_sub(earlier.0)))
+    }
+
+    /// Returns the moment **duration** from now. Checking **has_passed()** on the result gives
+    /// a non-blocking alternative to **sleep()**.
+    pub fn after(duration: Duration) -> Self {
+        Instant::now() + duration
+    }
+
+    /// Returns true if this moment is now or in the past.
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= *self
+    }
+
+    /// Returns the time elapsed since this moment.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the first moment at least **duration** after this one, or **None** on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let ns = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(pit::ns_to_ticks(ns)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// It will **panic** on overflow.
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
@@ -0,0 +1,110 @@
+// Software timers driven by the timer interrupt. Each registered callback runs in interrupt
+// context once its deadline arrives, either once or repeatedly.
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{pit, time};
+
+/// The maximum number of timers that can be pending at once.
+pub const MAX_TIMERS: usize = 32;
+
+/// Identifies a timer registered with **set_timeout()** or **set_interval()**, so that it can
+/// be cancelled. A handle remains harmless after its timer has finished; cancelling it then
+/// has no effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimerHandle {
+    slot: usize,
+    generation: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Timer {
+    due: u64,
+    period: Option<u64>,
+    callback: fn(),
+    generation: u32,
+}
+
+struct TimerTable {
+    timers: [Option<Timer>; MAX_TIMERS],
+    generation: u32,
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<TimerTable> = Mutex::new(TimerTable {
+        timers: [None; MAX_TIMERS],
+        generation: 0,
+    });
+}
+
+/// Calls **callback** once, from the timer interrupt, after at least **ms** milliseconds.
+/// Returns **None** if **MAX_TIMERS** timers are already pending.
+pub fn set_timeout(ms: u64, callback: fn()) -> Option<TimerHandle> {
+    add(pit::ms_to_ticks(ms), None, callback)
+}
+
+/// Calls **callback** from the timer interrupt every **ms** milliseconds, until cancelled.
+/// Periods shorter than one timer interrupt are rounded up to one interrupt.
+/// Returns **None** if **MAX_TIMERS** timers are already pending.
+pub fn set_interval(ms: u64, callback: fn()) -> Option<TimerHandle> {
+    let period = pit::ms_to_ticks(ms).max(1);
+    add(period, Some(period), callback)
+}
+
+/// Stops the given timer. Returns true if it was still pending.
+pub fn cancel(handle: TimerHandle) -> bool {
+    without_interrupts(|| {
+        let mut table = TIMERS.lock();
+        let slot = &mut table.timers[handle.slot];
+        if slot.is_some_and(|t| t.generation == handle.generation) {
+            *slot = None;
+            true
+        } else {
+            false
+        }
+    })
+}
+
+fn add(delay: u64, period: Option<u64>, callback: fn()) -> Option<TimerHandle> {
+    without_interrupts(|| {
+        let mut table = TIMERS.lock();
+        let slot = table.timers.iter().position(|t| t.is_none())?;
+        table.generation = table.generation.wrapping_add(1);
+        let generation = table.generation;
+        table.timers[slot] = Some(Timer {
+            // The current tick is already partly over, so the first deadline is one tick
+            // further out to guarantee that at least **delay** full ticks pass.
+            due: time::ticks().saturating_add(delay).saturating_add(1),
+            period,
+            callback,
+            generation,
+        });
+        Some(TimerHandle { slot, generation })
+    })
+}
+
+/// Called by the timer interrupt handler. Callbacks run after the table is unlocked, so that
+/// they may register or cancel timers themselves.
+pub(crate) fn fire_due() {
+    let now = time::ticks();
+    let mut due = [None; MAX_TIMERS];
+    {
+        let mut table = TIMERS.lock();
+        for (slot, fired) in table.timers.iter_mut().zip(due.iter_mut()) {
+            if let Some(timer) = slot {
+                if timer.due <= now {
+                    *fired = Some(timer.callback);
+                    match timer.period {
+                        Some(period) => timer.due += period,
+                        None => *slot = None,
+                    }
+                }
+            }
+        }
+    }
+    for callback in due.iter().flatten() {
+        callback();
+    }
+}
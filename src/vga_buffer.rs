@@ -7,19 +7,72 @@
 // - ColorCode::{foreground(), background()}
 // - Plot enum
 // - impl From for Color
+// - ScreenChar::inverted(), for the mouse pointer, and keeping the pointer in place when the
+//   Writer scrolls
+// - Writer::{position, set_position, plot_byte}, position(), set_position()
+// - Writer::row_position and the handling of \r, \t, backspace and form feed
+// - Interpretation of ANSI escape sequences by Writer
+// - Translation of characters through cp437.rs
+// - LineStyle, draw_hline(), draw_vline(), draw_box(), fill_rect()
+// - print!() goes through console.rs
+// - Hardware cursor control: set_cursor(), enable_cursor(), disable_cursor(), and keeping the
+//   cursor at the Writer's position
 
 use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
 
-const MIN_DRAWABLE: u8 = 0x20;
-const MAX_DRAWABLE: u8 = 0x7e;
+use crate::{cp437, mouse};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = b'\t';
+const FORM_FEED: u8 = 0x0c;
+const ESCAPE: u8 = 0x1b;
+const UNDRAWABLE: u8 = 0xfe;
+
+/// The distance between tab stops.
+pub const TAB_WIDTH: usize = 8;
+
+// The CRT controller's registers are reached by writing a register number to the index port,
+// then reading or writing the data port.
+const CRTC_INDEX_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CURSOR_START: u8 = 0x0A;
+const CURSOR_END: u8 = 0x0B;
+const CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CURSOR_LOCATION_LOW: u8 = 0x0F;
+const CURSOR_DISABLED: u8 = 0x20;
+const MAX_SCAN_LINE: u8 = 15;
+
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::Yellow, Color::Black);
+const BRIGHT: u8 = 0x08;
+
+// The eight ANSI colors, in the order of their SGR codes. Their bright versions are 8 higher.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+const MAX_CSI_PARAMS: usize = 8;
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
         column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
+        row_position: BUFFER_HEIGHT - 1,
+        color_code: DEFAULT_COLOR,
+        foreground: DEFAULT_COLOR.foreground() as u8,
+        bold: false,
+        escape: Escape::None,
+        saved_position: (0, 0),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
     });
 }
@@ -80,7 +133,7 @@ pub struct ColorCode(u8);
 
 #[allow(dead_code)]
 impl ColorCode {
-    pub fn new(foreground: Color, background: Color) -> ColorCode {
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
 
@@ -95,11 +148,22 @@ impl ColorCode {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
-struct ScreenChar {
+pub(crate) struct ScreenChar {
     ascii_character: u8,
     color_code: ColorCode,
 }
 
+impl ScreenChar {
+    // The same character with its foreground and background colors swapped.
+    pub(crate) fn inverted(self) -> ScreenChar {
+        let ColorCode(color) = self.color_code;
+        ScreenChar {
+            ascii_character: self.ascii_character,
+            color_code: ColorCode(color.rotate_right(4)),
+        }
+    }
+}
+
 pub const BUFFER_HEIGHT: usize = 25;
 pub const BUFFER_WIDTH: usize = 80;
 
@@ -108,36 +172,165 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// Writes text the way a terminal does. Writing starts on the bottom row, text wraps at the
+/// end of each row, and the screen scrolls up only when text continues past the bottom row.
+///
+/// Besides drawable characters, it understands these control characters:
+/// - **\n** moves to the start of the next row.
+/// - **\r** moves to the start of the current row.
+/// - **\t** moves to the next tab stop, every **TAB_WIDTH** columns.
+/// - **\x08** (backspace) moves back one column, to the end of the previous row if need be. It
+///   does not erase; write **"\x08 \x08"** to do so.
+/// - **\x0c** (form feed) clears the screen and moves to the top left.
+///
+/// It also interprets these ANSI escape sequences, so that text colored and positioned for a
+/// terminal looks the same on screen. **n** stands for an optional number.
+/// - **ESC[nA**, **ESC[nB**, **ESC[nC** and **ESC[nD** move up, down, right and left.
+/// - **ESC[nG** moves to column **n**, and **ESC[n;mH** (or **f**) to row **n**, column **m**,
+///   counting from 1.
+/// - **ESC[nJ** erases from the cursor to the end of the screen (0), from the start of the
+///   screen to the cursor (1), or the whole screen (2).
+/// - **ESC[nK** does the same within the cursor's row.
+/// - **ESC[n;...m** sets colors: 0 resets them, 1 brightens the foreground, 22 undoes that,
+///   30-37 and 90-97 set the foreground, 40-47 and 100-107 the background, and 39 and 49
+///   restore the default foreground and background.
+/// - **ESC[s** and **ESC7** save the position; **ESC[u** and **ESC8** restore it.
+///
+/// Other escape sequences are ignored. Any other character is shown as ■.
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
     color_code: ColorCode,
+    // The foreground chosen by the escape sequences, before bold brightens it.
+    foreground: u8,
+    bold: bool,
+    escape: Escape,
+    saved_position: (usize, usize),
     buffer: &'static mut Buffer,
 }
 
+// Progress through an escape sequence.
+#[derive(Copy, Clone)]
+enum Escape {
+    None,
+    // ESC has been seen.
+    Started,
+    // ESC [ has been seen, followed by parameters separated by semicolons. Missing parameters
+    // are 0. Once **index** reaches **MAX_CSI_PARAMS**, further parameters are ignored.
+    Csi {
+        params: [u16; MAX_CSI_PARAMS],
+        index: usize,
+    },
+}
+
 #[allow(dead_code)]
 impl Writer {
-    fn plot(&mut self, col: usize, row: usize, content: ScreenChar) {
+    pub(crate) fn plot(&mut self, col: usize, row: usize, content: ScreenChar) {
         self.buffer.chars[row][col].write(content);
     }
 
-    fn peek(&self, col: usize, row: usize) -> ScreenChar {
+    pub(crate) fn peek(&self, col: usize, row: usize) -> ScreenChar {
         self.buffer.chars[row][col].read()
     }
 
+    /// Returns the (column, row) where the next character will be written. The column equals
+    /// **BUFFER_WIDTH** once a row is full; the next character then goes on the following row.
+    pub fn position(&self) -> (usize, usize) {
+        (self.column_position, self.row_position)
+    }
+
+    /// Sets where the next character will be written.
+    /// It will **panic** on an illegal row or column.
+    pub fn set_position(&mut self, col: usize, row: usize) {
+        assert!(
+            col <= BUFFER_WIDTH && row < BUFFER_HEIGHT,
+            "Position ({}, {}) is off the screen",
+            col,
+            row
+        );
+        self.column_position = col;
+        self.row_position = row;
+        self.sync_cursor();
+    }
+
+    // Once a row is full, the next character goes at the start of a new row, but the cursor
+    // cannot go there yet, so it waits at the end of the row.
+    fn sync_cursor(&self) {
+        move_cursor(
+            self.column_position.min(BUFFER_WIDTH - 1),
+            self.row_position,
+        );
+    }
+
+    // Draws with the writer's color, without moving the position.
+    pub(crate) fn plot_byte(&mut self, col: usize, row: usize, byte: u8) {
+        self.plot(
+            col,
+            row,
+            ScreenChar {
+                ascii_character: byte,
+                color_code: self.color_code,
+            },
+        );
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
+        self.put_byte(byte);
+        self.sync_cursor();
+    }
+
+    // Moving the hardware cursor takes four port writes, so this leaves it to the caller, which
+    // moves it once after a whole string.
+    fn put_byte(&mut self, byte: u8) {
+        if !matches!(self.escape, Escape::None) {
+            self.escape_byte(byte);
+            return;
+        }
         match byte {
+            ESCAPE => self.escape = Escape::Started,
             b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            TAB => self.tab(),
+            BACKSPACE => self.backspace(),
+            FORM_FEED => self.form_feed(),
             byte => self.write_char(byte),
         }
     }
 
+    fn tab(&mut self) {
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
+        }
+        let stop = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+        while self.column_position < stop.min(BUFFER_WIDTH) {
+            self.write_char(b' ');
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.column_position > 0 {
+            self.column_position = self.column_position.min(BUFFER_WIDTH) - 1;
+        } else if self.row_position > 0 {
+            self.row_position -= 1;
+            self.column_position = BUFFER_WIDTH - 1;
+        }
+    }
+
+    fn form_feed(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+        self.row_position = 0;
+    }
+
     // GJF - Refactored out of write_byte()
     fn write_char(&mut self, byte: u8) {
         if self.column_position >= BUFFER_WIDTH {
             self.new_line();
         }
 
-        let row = BUFFER_HEIGHT - 1;
+        let row = self.row_position;
         let col = self.column_position;
 
         self.plot(
@@ -152,6 +345,15 @@ impl Writer {
     }
 
     fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position + 1 < BUFFER_HEIGHT {
+            self.row_position += 1;
+            return;
+        }
+        mouse::around_scroll(self, Writer::scroll_up);
+    }
+
+    fn scroll_up(&mut self) {
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
                 let character = self.buffer.chars[row][col].read();
@@ -159,35 +361,160 @@ impl Writer {
             }
         }
         self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
     }
 
     fn clear_row(&mut self, row: usize) {
+        self.erase(row, 0, BUFFER_WIDTH);
+    }
+
+    // Blanks columns **start** up to **end** of the given row.
+    fn erase(&mut self, row: usize, start: usize, end: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
+        for col in start..end {
             self.buffer.chars[row][col].write(blank);
         }
     }
 
+    fn escape_byte(&mut self, byte: u8) {
+        self.escape = match (self.escape, byte) {
+            (Escape::Started, b'[') => Escape::Csi {
+                params: [0; MAX_CSI_PARAMS],
+                index: 0,
+            },
+            (Escape::Started, b'7') => {
+                self.saved_position = self.position();
+                Escape::None
+            }
+            (Escape::Started, b'8') => {
+                (self.column_position, self.row_position) = self.saved_position;
+                Escape::None
+            }
+            (Escape::Csi { mut params, index }, b'0'..=b'9') => {
+                if let Some(param) = params.get_mut(index) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add((byte - b'0') as u16);
+                }
+                Escape::Csi { params, index }
+            }
+            (Escape::Csi { params, index }, b';') => Escape::Csi {
+                params,
+                index: (index + 1).min(MAX_CSI_PARAMS),
+            },
+            // Other parameter and intermediate bytes, such as the ? of private sequences, do
+            // not matter to any of the supported sequences.
+            (escape @ Escape::Csi { .. }, 0x20..=0x3f) => escape,
+            (Escape::Csi { params, index }, 0x40..=0x7e) => {
+                self.csi(byte, &params[..(index + 1).min(MAX_CSI_PARAMS)]);
+                Escape::None
+            }
+            _ => Escape::None,
+        };
+    }
+
+    fn csi(&mut self, command: u8, params: &[u16]) {
+        // Movement counts and coordinates of 0 mean the same as 1.
+        let count = |i: usize| params.get(i).copied().unwrap_or(0).max(1) as usize;
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let row = self.row_position;
+        match command {
+            b'A' => self.move_to(col, row.saturating_sub(count(0))),
+            b'B' => self.move_to(col, row + count(0)),
+            b'C' => self.move_to(col + count(0), row),
+            b'D' => self.move_to(col.saturating_sub(count(0)), row),
+            b'G' => self.move_to(count(0) - 1, row),
+            b'H' | b'f' => self.move_to(count(1) - 1, count(0) - 1),
+            b'J' => match params[0] {
+                0 => {
+                    self.erase(row, col, BUFFER_WIDTH);
+                    (row + 1..BUFFER_HEIGHT).for_each(|r| self.clear_row(r));
+                }
+                1 => {
+                    (0..row).for_each(|r| self.clear_row(r));
+                    self.erase(row, 0, col + 1);
+                }
+                _ => (0..BUFFER_HEIGHT).for_each(|r| self.clear_row(r)),
+            },
+            b'K' => match params[0] {
+                0 => self.erase(row, col, BUFFER_WIDTH),
+                1 => self.erase(row, 0, col + 1),
+                _ => self.clear_row(row),
+            },
+            b'm' => params
+                .iter()
+                .for_each(|param| self.select_graphic_rendition(*param)),
+            b's' => self.saved_position = (col, row),
+            b'u' => (self.column_position, self.row_position) = self.saved_position,
+            _ => {}
+        }
+    }
+
+    // Moves to the given position, stopping at the edges of the screen.
+    fn move_to(&mut self, col: usize, row: usize) {
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+    }
+
+    fn select_graphic_rendition(&mut self, param: u16) {
+        let ColorCode(color) = self.color_code;
+        let mut background = color >> 4;
+        match param {
+            0 => {
+                self.bold = false;
+                self.foreground = DEFAULT_COLOR.foreground() as u8;
+                background = DEFAULT_COLOR.background() as u8;
+            }
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.foreground = ANSI_COLORS[param as usize - 30] as u8,
+            39 => self.foreground = DEFAULT_COLOR.foreground() as u8,
+            40..=47 => background = ANSI_COLORS[param as usize - 40] as u8,
+            49 => background = DEFAULT_COLOR.background() as u8,
+            90..=97 => self.foreground = ANSI_COLORS[param as usize - 90] as u8 | BRIGHT,
+            100..=107 => background = ANSI_COLORS[param as usize - 100] as u8 | BRIGHT,
+            _ => {}
+        }
+        // Bold brightens the chosen foreground without forgetting it, so that 22 restores it
+        // even when it was already bright, as the default Yellow is.
+        let foreground = if self.bold {
+            self.foreground | BRIGHT
+        } else {
+            self.foreground
+        };
+        self.color_code = ColorCode(background << 4 | foreground);
+    }
+
+    /// Writes **s**, translating each character into code page 437. Characters the VGA font
+    /// cannot draw are shown as ■.
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                MIN_DRAWABLE..=MAX_DRAWABLE | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
+        for c in s.chars() {
+            match c {
+                ' '..='~' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' | '\u{1b}' => {
+                    self.put_byte(c as u8)
+                }
+                _ => self.write_glyph(cp437::from_char(c).unwrap_or(UNDRAWABLE)),
             }
         }
+        self.sync_cursor();
+    }
+
+    // Some code page 437 symbols share their bytes with control characters, so they bypass
+    // put_byte().
+    fn write_glyph(&mut self, byte: u8) {
+        self.escape = Escape::None;
+        self.write_char(byte);
     }
 }
 
 #[allow(dead_code)]
+/// Returns true if the VGA font can draw **c**. This includes printable ASCII and the other
+/// characters of [code page 437](https://en.wikipedia.org/wiki/Code_page_437), such as
+/// accented letters, box-drawing characters and card suits.
 pub fn is_drawable(c: char) -> bool {
-    match c as u8 {
-        MIN_DRAWABLE..=MAX_DRAWABLE => true,
-        _ => false,
-    }
+    cp437::from_char(c).is_some()
 }
 
 impl fmt::Write for Writer {
@@ -199,7 +526,7 @@ impl fmt::Write for Writer {
 
 #[macro_export]
 macro_rules! print {
-    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
 }
 
 #[macro_export]
@@ -211,13 +538,82 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
     });
 }
 
+fn write_crtc(register: u8, value: u8) {
+    let mut index: Port<u8> = Port::new(CRTC_INDEX_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        index.write(register);
+        data.write(value);
+    }
+}
+
+fn read_crtc(register: u8) -> u8 {
+    let mut index: Port<u8> = Port::new(CRTC_INDEX_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        index.write(register);
+        data.read()
+    }
+}
+
+fn move_cursor(col: usize, row: usize) {
+    let position = (row * BUFFER_WIDTH + col) as u16;
+    write_crtc(CURSOR_LOCATION_LOW, position as u8);
+    write_crtc(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+}
+
+/// Returns the (column, row) where **print!()** will write next.
+pub fn position() -> (usize, usize) {
+    interrupts::without_interrupts(|| WRITER.lock().position())
+}
+
+/// Sets where **print!()** will write next, moving the hardware cursor there.
+/// It will **panic** on an illegal row or column.
+pub fn set_position(col: usize, row: usize) {
+    interrupts::without_interrupts(|| WRITER.lock().set_position(col, row));
+}
+
+/// Moves the blinking hardware cursor to the given location. It stays there until the next
+/// **print!()**, which moves it to where the next character will appear.
+/// It will **panic** on an illegal row or column.
+pub fn set_cursor(col: usize, row: usize) {
+    assert!(
+        col < BUFFER_WIDTH && row < BUFFER_HEIGHT,
+        "Cursor position ({}, {}) is off the screen",
+        col,
+        row
+    );
+    interrupts::without_interrupts(|| move_cursor(col, row));
+}
+
+/// Shows the hardware cursor, filling scan lines **start** through **end** of the character
+/// cell. Each cell has 16 scan lines, numbered from the top; **enable_cursor(14, 15)** gives
+/// the usual underline, and **enable_cursor(0, 15)** a full block.
+/// It will **panic** if **start** or **end** is above 15.
+pub fn enable_cursor(start: u8, end: u8) {
+    assert!(
+        start <= MAX_SCAN_LINE && end <= MAX_SCAN_LINE,
+        "Cursor scan lines {}..{} out of range",
+        start,
+        end
+    );
+    interrupts::without_interrupts(|| {
+        write_crtc(CURSOR_START, read_crtc(CURSOR_START) & 0xC0 | start);
+        write_crtc(CURSOR_END, read_crtc(CURSOR_END) & 0xE0 | end);
+    });
+}
+
+/// Hides the hardware cursor.
+pub fn disable_cursor() {
+    interrupts::without_interrupts(|| write_crtc(CURSOR_START, CURSOR_DISABLED));
+}
+
 #[allow(dead_code)]
 /// Clears one row of the VGA buffer, setting everything to the background color specified.
 /// It will **panic** on an illegal row.
@@ -239,10 +635,11 @@ pub fn clear_screen() {
 #[allow(dead_code)]
 /// Displays the specified string at the given coordinates.
 /// If the string exceeds the width of the buffer, it will be truncated.
+/// Characters are translated as by **plot()**.
 /// An illegal row will **panic**.
 pub fn plot_str(s: &str, col: usize, row: usize, color: ColorCode) -> usize {
     use crate::serial_println;
-    let end = BUFFER_WIDTH.min(col + s.len());
+    let end = BUFFER_WIDTH.min(col + s.chars().count());
     for (c, chr) in (col..end).zip(s.chars()) {
         serial_println!("Plotting {} ({},{})", chr, c, row);
         plot(chr, c, row, color);
@@ -264,13 +661,15 @@ pub fn clear(num_spaces: usize, col: usize, row: usize, color: ColorCode) -> usi
 
 #[allow(dead_code)]
 /// Plots the given character at the given location with the given color.
+/// The character is translated into code page 437; if **is_drawable()** rejects it, ■ is
+/// plotted instead.
 /// It will **panic** on an illegal row or column.
 pub fn plot(c: char, col: usize, row: usize, color: ColorCode) {
     WRITER.lock().plot(
         col,
         row,
         ScreenChar {
-            ascii_character: c as u8,
+            ascii_character: cp437::from_char(c).unwrap_or(UNDRAWABLE),
             color_code: color,
         },
     );
@@ -365,7 +764,126 @@ pub fn plot_num(num: isize, col: usize, row: usize, color: ColorCode) -> usize {
 /// It will **panic** given an illegal row or column.
 pub fn peek(col: usize, row: usize) -> (char, ColorCode) {
     let result = WRITER.lock().peek(col, row);
-    (result.ascii_character as char, result.color_code)
+    (cp437::to_char(result.ascii_character), result.color_code)
+}
+
+/// The line characters used by **draw_hline()**, **draw_vline()** and **draw_box()**.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineStyle {
+    /// ─ │ ┌ ┐ └ ┘
+    Single,
+    /// ═ ║ ╔ ╗ ╚ ╝
+    Double,
+}
+
+struct LineChars {
+    horizontal: char,
+    vertical: char,
+    top_left: char,
+    top_right: char,
+    bottom_left: char,
+    bottom_right: char,
+}
+
+impl LineStyle {
+    fn chars(self) -> LineChars {
+        match self {
+            LineStyle::Single => LineChars {
+                horizontal: '─',
+                vertical: '│',
+                top_left: '┌',
+                top_right: '┐',
+                bottom_left: '└',
+                bottom_right: '┘',
+            },
+            LineStyle::Double => LineChars {
+                horizontal: '═',
+                vertical: '║',
+                top_left: '╔',
+                top_right: '╗',
+                bottom_left: '╚',
+                bottom_right: '╝',
+            },
+        }
+    }
+}
+
+fn assert_fits(col: usize, row: usize, width: usize, height: usize) {
+    assert!(
+        col + width <= BUFFER_WIDTH && row + height <= BUFFER_HEIGHT,
+        "{}x{} area at ({}, {}) does not fit on the screen",
+        width,
+        height,
+        col,
+        row
+    );
+}
+
+/// Draws a horizontal line **len** columns long, starting at the given location and going
+/// right. It will **panic** if the line does not fit on the screen.
+pub fn draw_hline(col: usize, row: usize, len: usize, style: LineStyle, color: ColorCode) {
+    assert_fits(col, row, len, 1);
+    let horizontal = style.chars().horizontal;
+    for c in col..col + len {
+        plot(horizontal, c, row, color);
+    }
+}
+
+/// Draws a vertical line **len** rows long, starting at the given location and going down.
+/// It will **panic** if the line does not fit on the screen.
+pub fn draw_vline(col: usize, row: usize, len: usize, style: LineStyle, color: ColorCode) {
+    assert_fits(col, row, 1, len);
+    let vertical = style.chars().vertical;
+    for r in row..row + len {
+        plot(vertical, col, r, color);
+    }
+}
+
+/// Draws the outline of a box **width** columns wide and **height** rows high, with its top
+/// left corner at the given location. The inside of the box is left alone; use
+/// **fill_rect()** first to clear it. A box only one column wide or one row high is drawn as
+/// a line. It will **panic** if the box does not fit on the screen.
+pub fn draw_box(
+    col: usize,
+    row: usize,
+    width: usize,
+    height: usize,
+    style: LineStyle,
+    color: ColorCode,
+) {
+    assert_fits(col, row, width, height);
+    if width < 2 || height < 2 {
+        if width == 1 {
+            draw_vline(col, row, height, style, color);
+        } else if height == 1 {
+            draw_hline(col, row, width, style, color);
+        }
+        return;
+    }
+    let chars = style.chars();
+    let right = col + width - 1;
+    let bottom = row + height - 1;
+    draw_hline(col + 1, row, width - 2, style, color);
+    draw_hline(col + 1, bottom, width - 2, style, color);
+    draw_vline(col, row + 1, height - 2, style, color);
+    draw_vline(right, row + 1, height - 2, style, color);
+    plot(chars.top_left, col, row, color);
+    plot(chars.top_right, right, row, color);
+    plot(chars.bottom_left, col, bottom, color);
+    plot(chars.bottom_right, right, bottom, color);
+}
+
+/// Fills a rectangle **width** columns wide and **height** rows high with the character **c**,
+/// with its top left corner at the given location. Filling with ' ' clears the rectangle to
+/// the background color of **color**; '█', '▓', '▒' and '░' give solid and shaded fills.
+/// It will **panic** if the rectangle does not fit on the screen.
+pub fn fill_rect(col: usize, row: usize, width: usize, height: usize, c: char, color: ColorCode) {
+    assert_fits(col, row, width, height);
+    for r in row..row + height {
+        for x in col..col + width {
+            plot(c, x, r, color);
+        }
+    }
 }
 
 #[allow(dead_code)]